@@ -0,0 +1,526 @@
+//! Generates the event structs, their discriminators and their row mappings
+//! from the Anchor IDL of the program, see `src/events.rs`. Discriminators are
+//! derived from the event names, the ones listed in the IDL are only checked
+//! by the tests of `events.rs`. Events with a message of their own in the
+//! `Event.event` oneof of the proto also get a conversion into it, and the
+//! same row mapping on it.
+
+use serde_json::Value;
+use std::fmt::Write;
+use std::path::Path;
+
+const IDL: &str = "idl/gummy_staking.json";
+const PROTO: &str = "proto/event.proto";
+
+fn main() {
+    println!("cargo:rerun-if-changed={IDL}");
+    println!("cargo:rerun-if-changed={PROTO}");
+    let idl: Value = serde_json::from_str(
+        &std::fs::read_to_string(IDL).unwrap_or_else(|e| panic!("reading {IDL}: {e}")),
+    )
+    .unwrap_or_else(|e| panic!("parsing {IDL}: {e}"));
+
+    let mut events = Vec::new();
+    for event in array(&idl, "events") {
+        let name = string(event, "name");
+        // IDLs of Anchor before 0.30 list the fields with the event.
+        let fields = match event.get("fields") {
+            Some(_) => array(event, "fields"),
+            None => {
+                let definition = array(&idl, "types")
+                    .iter()
+                    .find(|definition| string(definition, "name") == name)
+                    .unwrap_or_else(|| panic!("{IDL}: no type for event {name}"));
+                array(&definition["type"], "fields")
+            }
+        };
+        let fields = fields
+            .iter()
+            .map(|field| {
+                let field_name = string(field, "name");
+                let field_type = FieldType::parse(&field["type"])
+                    .unwrap_or_else(|| panic!("{IDL}: unsupported type of {name}.{field_name}"));
+                (field_name, field_type)
+            })
+            .collect();
+        events.push(Event { name, fields });
+    }
+    let messages = oneof_messages(
+        &std::fs::read_to_string(PROTO).unwrap_or_else(|e| panic!("reading {PROTO}: {e}")),
+    );
+
+    let out = Path::new(&std::env::var("OUT_DIR").unwrap()).join("events.rs");
+    std::fs::write(&out, generate(&events, &messages)).unwrap();
+}
+
+fn array<'a>(value: &'a Value, key: &str) -> &'a Vec<Value> {
+    value[key]
+        .as_array()
+        .unwrap_or_else(|| panic!("{IDL}: '{key}' is not an array"))
+}
+
+fn string<'a>(value: &'a Value, key: &str) -> &'a str {
+    value[key]
+        .as_str()
+        .unwrap_or_else(|| panic!("{IDL}: '{key}' is not a string"))
+}
+
+/// Message types of the `oneof event` of the proto.
+fn oneof_messages(proto: &str) -> Vec<String> {
+    let (_, oneof) = proto
+        .split_once("oneof event {")
+        .unwrap_or_else(|| panic!("{PROTO}: no 'oneof event'"));
+    let (oneof, _) = oneof
+        .split_once('}')
+        .unwrap_or_else(|| panic!("{PROTO}: unterminated 'oneof event'"));
+    oneof
+        .lines()
+        .filter_map(|line| line.split_whitespace().next())
+        .filter(|word| !word.starts_with("//"))
+        .map(str::to_string)
+        .collect()
+}
+
+struct Event<'a> {
+    name: &'a str,
+    fields: Vec<(&'a str, FieldType)>,
+}
+
+enum FieldType {
+    Bool,
+    /// Integers that rows accept as is.
+    Integer(&'static str),
+    /// 128 bit integers, written to rows as strings.
+    WideInteger(&'static str),
+    String,
+    Bytes,
+    Pubkey,
+    Option(Box<FieldType>),
+    Vec(Box<FieldType>),
+    Array(Box<FieldType>, u64),
+}
+
+impl FieldType {
+    fn parse(value: &Value) -> Option<FieldType> {
+        if let Some(name) = value.as_str() {
+            return Some(match name {
+                "bool" => FieldType::Bool,
+                "u8" => FieldType::Integer("u8"),
+                "u16" => FieldType::Integer("u16"),
+                "u32" => FieldType::Integer("u32"),
+                "u64" => FieldType::Integer("u64"),
+                "i8" => FieldType::Integer("i8"),
+                "i16" => FieldType::Integer("i16"),
+                "i32" => FieldType::Integer("i32"),
+                "i64" => FieldType::Integer("i64"),
+                "u128" => FieldType::WideInteger("u128"),
+                "i128" => FieldType::WideInteger("i128"),
+                "string" => FieldType::String,
+                "bytes" => FieldType::Bytes,
+                "pubkey" | "publicKey" => FieldType::Pubkey,
+                _ => return None,
+            });
+        }
+        if let Some(inner) = value.get("option") {
+            return Some(FieldType::Option(Box::new(FieldType::parse(inner)?)));
+        }
+        if let Some(inner) = value.get("vec") {
+            return Some(FieldType::Vec(Box::new(FieldType::parse(inner)?)));
+        }
+        if let Some([inner, len]) = value
+            .get("array")
+            .and_then(|a| a.as_array())
+            .map(|a| &a[..])
+        {
+            return Some(FieldType::Array(
+                Box::new(FieldType::parse(inner)?),
+                len.as_u64()?,
+            ));
+        }
+        None
+    }
+
+    fn rust_type(&self) -> String {
+        match self {
+            FieldType::Bool => "bool".to_string(),
+            FieldType::Integer(name) | FieldType::WideInteger(name) => name.to_string(),
+            FieldType::String => "String".to_string(),
+            FieldType::Bytes => "Vec<u8>".to_string(),
+            FieldType::Pubkey => "Pubkey".to_string(),
+            FieldType::Option(inner) => format!("Option<{}>", inner.rust_type()),
+            FieldType::Vec(inner) => format!("Vec<{}>", inner.rust_type()),
+            FieldType::Array(inner, len) => format!("[{}; {len}]", inner.rust_type()),
+        }
+    }
+
+    /// Serialized size, if it does not depend on the value.
+    fn size(&self) -> Option<u64> {
+        match self {
+            FieldType::Bool => Some(1),
+            FieldType::Integer(name) | FieldType::WideInteger(name) => {
+                Some(name[1..].parse::<u64>().unwrap() / 8)
+            }
+            FieldType::Pubkey => Some(32),
+            FieldType::Array(inner, len) => Some(inner.size()? * len),
+            FieldType::String | FieldType::Bytes | FieldType::Option(_) | FieldType::Vec(_) => None,
+        }
+    }
+
+    /// The `ColumnType` of the column of the field.
+    fn column_type(&self) -> String {
+        match self {
+            FieldType::Bool => "ColumnType::Bool".to_string(),
+            FieldType::Integer(name) | FieldType::WideInteger(name) => format!(
+                "ColumnType::Integer {{ signed: {}, bits: {} }}",
+                name.starts_with('i'),
+                &name[1..]
+            ),
+            FieldType::Option(inner) => inner.column_type(),
+            FieldType::String
+            | FieldType::Bytes
+            | FieldType::Pubkey
+            | FieldType::Vec(_)
+            | FieldType::Array(..) => "ColumnType::Text".to_string(),
+        }
+    }
+
+    /// Expression converting `value`, an owned field, into the field of the
+    /// proto message: addresses and 128 bit integers are strings, and smaller
+    /// integers are widened to 32 bits.
+    fn message_value(&self, value: &str) -> String {
+        match self {
+            FieldType::Integer("u8" | "u16" | "i8" | "i16") => format!("{value}.into()"),
+            FieldType::Bool | FieldType::Integer(_) | FieldType::String | FieldType::Bytes => {
+                value.to_string()
+            }
+            FieldType::WideInteger(_) | FieldType::Pubkey => format!("{value}.to_string()"),
+            FieldType::Option(inner) => {
+                format!("{value}.map(|value| {})", inner.message_value("value"))
+            }
+            FieldType::Vec(inner) | FieldType::Array(inner, _) => match **inner {
+                FieldType::Integer("u8") => format!("{value}.to_vec()"),
+                _ => format!(
+                    "{value}.into_iter().map(|value| {}).collect()",
+                    inner.message_value("value")
+                ),
+            },
+        }
+    }
+
+    /// Like `column_value`, for a reference to a field of the proto message,
+    /// so that its rows match those of the event.
+    fn message_column_value(&self, value: &str) -> String {
+        match self {
+            FieldType::Bool | FieldType::Integer(_) => format!("*{value}"),
+            FieldType::WideInteger(_) | FieldType::Pubkey | FieldType::String => {
+                format!("{value}.clone()")
+            }
+            FieldType::Bytes => format!("Hex({value}).to_string()"),
+            FieldType::Option(inner) => format!(
+                "{value}.as_ref().map(|value| {})",
+                inner.message_column_value("value")
+            ),
+            FieldType::Vec(inner) | FieldType::Array(inner, _) => match **inner {
+                FieldType::Integer("u8") => format!("Hex({value}).to_string()"),
+                _ => format!(
+                    "{value}.iter().map(|value| ({}).to_string()).collect::<Vec<_>>().join(\",\")",
+                    inner.message_column_value("value")
+                ),
+            },
+        }
+    }
+
+    /// Expression converting `value`, a reference to a field, into a row
+    /// value. Byte strings are written as hex, and other sequences as their
+    /// comma separated elements.
+    fn column_value(&self, value: &str) -> String {
+        match self {
+            FieldType::Bool | FieldType::Integer(_) => format!("*{value}"),
+            FieldType::WideInteger(_) | FieldType::Pubkey => format!("{value}.to_string()"),
+            FieldType::String => format!("{value}.clone()"),
+            FieldType::Bytes => format!("Hex({value}).to_string()"),
+            FieldType::Option(inner) => format!(
+                "{value}.as_ref().map(|value| {})",
+                inner.column_value("value")
+            ),
+            FieldType::Vec(inner) | FieldType::Array(inner, _) => match **inner {
+                FieldType::Integer("u8") => format!("Hex({value}).to_string()"),
+                _ => format!(
+                    "{value}.iter().map(|value| ({}).to_string()).collect::<Vec<_>>().join(\",\")",
+                    inner.column_value("value")
+                ),
+            },
+        }
+    }
+}
+
+fn generate(events: &[Event], messages: &[String]) -> String {
+    let mut code = String::new();
+    writeln!(code, "// Generated by build.rs from {IDL}, do not edit.").unwrap();
+
+    for event in events {
+        writeln!(
+            code,
+            "\npub const DISCRIMINATOR_{}: &[u8] = &discriminator(\"event\", \"{}\");",
+            screaming_snake_case(event.name),
+            event.name
+        )
+        .unwrap();
+        writeln!(
+            code,
+            "#[derive(BorshDeserialize, BorshSerialize, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]"
+        )
+        .unwrap();
+        writeln!(code, "pub struct {} {{", event.name).unwrap();
+        for (name, field_type) in event.fields.iter() {
+            writeln!(code, "    pub {name}: {},", field_type.rust_type()).unwrap();
+        }
+        writeln!(code, "}}").unwrap();
+
+        writeln!(code, "impl {} {{", event.name).unwrap();
+        let size = event
+            .fields
+            .iter()
+            .map(|(_, field_type)| field_type.size())
+            .sum::<Option<u64>>();
+        writeln!(
+            code,
+            "    /// Serialized size, unless it depends on the values.\n    pub const SIZE: Option<usize> = {};",
+            match size {
+                Some(size) => format!("Some({size})"),
+                None => "None".to_string(),
+            }
+        )
+        .unwrap();
+        writeln!(
+            code,
+            "    pub(crate) fn set_columns<'a, R: Row>(&self, row: &'a mut R) -> &'a mut R {{"
+        )
+        .unwrap();
+        let names: Vec<&str> = event.fields.iter().map(|(name, _)| *name).collect();
+        writeln!(
+            code,
+            "        let {} {{ {} }} = self;",
+            event.name,
+            names.join(", ")
+        )
+        .unwrap();
+        writeln!(code, "        row").unwrap();
+        for (name, field_type) in event.fields.iter() {
+            let setter = match field_type {
+                FieldType::Option(_) => "set_if_some",
+                _ => "set",
+            };
+            writeln!(
+                code,
+                "            .{setter}(\"{name}\", {})",
+                field_type.column_value(name)
+            )
+            .unwrap();
+        }
+        writeln!(code, "    }}\n}}").unwrap();
+
+        if messages.iter().any(|message| message == event.name) {
+            writeln!(
+                code,
+                "impl From<{0}> for crate::pb::sf::solana::event::v1::{0} {{",
+                event.name
+            )
+            .unwrap();
+            writeln!(code, "    fn from(event: {}) -> Self {{", event.name).unwrap();
+            writeln!(code, "        Self {{").unwrap();
+            for (name, field_type) in event.fields.iter() {
+                writeln!(
+                    code,
+                    "            {name}: {},",
+                    field_type.message_value(&format!("event.{name}"))
+                )
+                .unwrap();
+            }
+            writeln!(code, "        }}\n    }}\n}}").unwrap();
+
+            // `build_tables` writes some events, such as short codes, to
+            // tables of their own shape.
+            writeln!(
+                code,
+                "#[allow(dead_code)]\nimpl crate::pb::sf::solana::event::v1::{} {{",
+                event.name
+            )
+            .unwrap();
+            writeln!(
+                code,
+                "    /// The columns `{}::set_columns` writes.\n    pub(crate) fn set_columns<'a, R: Row>(&self, row: &'a mut R) -> &'a mut R {{",
+                event.name
+            )
+            .unwrap();
+            writeln!(
+                code,
+                "        let Self {{ {}, .. }} = self;",
+                names.join(", ")
+            )
+            .unwrap();
+            writeln!(code, "        row").unwrap();
+            for (name, field_type) in event.fields.iter() {
+                let setter = match field_type {
+                    FieldType::Option(_) => "set_if_some",
+                    _ => "set",
+                };
+                writeln!(
+                    code,
+                    "            .{setter}(\"{name}\", {})",
+                    field_type.message_column_value(name)
+                )
+                .unwrap();
+            }
+            writeln!(code, "    }}\n}}").unwrap();
+        }
+    }
+
+    writeln!(code, "\npub const EVENTS: &[EventInfo] = &[").unwrap();
+    for event in events {
+        let columns: Vec<String> = event
+            .fields
+            .iter()
+            .map(|(name, field_type)| {
+                format!(
+                    "Column {{ name: \"{name}\", column_type: {}, nullable: {} }}",
+                    field_type.column_type(),
+                    matches!(field_type, FieldType::Option(_))
+                )
+            })
+            .collect();
+        writeln!(
+            code,
+            "    EventInfo {{ name: \"{0}\", discriminator: DISCRIMINATOR_{1}, deserialize: |reader| {0}::deserialize_reader(reader).map(GummyEvent::{0}), message: {2}, columns: &[{3}] }},",
+            event.name,
+            screaming_snake_case(event.name),
+            messages.iter().any(|message| message == event.name),
+            columns.join(", "),
+        )
+        .unwrap();
+    }
+    writeln!(code, "];").unwrap();
+
+    writeln!(
+        code,
+        "\n#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]\npub enum GummyEvent {{"
+    )
+    .unwrap();
+    for event in events {
+        writeln!(code, "    {0}({0}),", event.name).unwrap();
+    }
+    writeln!(code, "}}").unwrap();
+    writeln!(code, "impl GummyEvent {{").unwrap();
+    writeln!(
+        code,
+        "    pub fn name(&self) -> &'static str {{\n        match self {{"
+    )
+    .unwrap();
+    for event in events {
+        writeln!(
+            code,
+            "            GummyEvent::{0}(_) => \"{0}\",",
+            event.name
+        )
+        .unwrap();
+    }
+    writeln!(code, "        }}\n    }}").unwrap();
+    writeln!(
+        code,
+        "    pub fn discriminator(&self) -> &'static [u8] {{\n        match self {{"
+    )
+    .unwrap();
+    for event in events {
+        writeln!(
+            code,
+            "            GummyEvent::{}(_) => DISCRIMINATOR_{},",
+            event.name,
+            screaming_snake_case(event.name)
+        )
+        .unwrap();
+    }
+    writeln!(code, "        }}\n    }}").unwrap();
+    writeln!(
+        code,
+        "    pub(crate) fn set_columns<'a, R: Row>(&self, row: &'a mut R) -> &'a mut R {{\n        match self {{"
+    )
+    .unwrap();
+    for event in events {
+        writeln!(
+            code,
+            "            GummyEvent::{}(event) => event.set_columns(row),",
+            event.name
+        )
+        .unwrap();
+    }
+    writeln!(code, "        }}\n    }}\n}}").unwrap();
+
+    writeln!(
+        code,
+        "\n/// Events without a message of their own become an `IdlEvent`."
+    )
+    .unwrap();
+    writeln!(
+        code,
+        "impl From<GummyEvent> for crate::pb::sf::solana::event::v1::event::Event {{"
+    )
+    .unwrap();
+    writeln!(
+        code,
+        "    fn from(event: GummyEvent) -> Self {{\n        match event {{"
+    )
+    .unwrap();
+    for event in events {
+        if messages.iter().any(|message| message == event.name) {
+            writeln!(
+                code,
+                "            GummyEvent::{0}(event) => Self::{0}(event.into()),",
+                event.name
+            )
+            .unwrap();
+        } else {
+            writeln!(
+                code,
+                "            GummyEvent::{0}(event) => Self::IdlEvent(crate::pb::sf::solana::event::v1::IdlEvent {{ name: \"{0}\".to_string(), data: borsh::to_vec(&event).unwrap() }}),",
+                event.name
+            )
+            .unwrap();
+        }
+    }
+    writeln!(code, "        }}\n    }}\n}}").unwrap();
+
+    writeln!(
+        code,
+        "\n/// Serialized as Anchor emits events: the discriminator, then the event."
+    )
+    .unwrap();
+    writeln!(code, "impl BorshSerialize for GummyEvent {{").unwrap();
+    writeln!(
+        code,
+        "    fn serialize<W: borsh::io::Write>(&self, writer: &mut W) -> borsh::io::Result<()> {{"
+    )
+    .unwrap();
+    writeln!(code, "        writer.write_all(self.discriminator())?;").unwrap();
+    writeln!(code, "        match self {{").unwrap();
+    for event in events {
+        writeln!(
+            code,
+            "            GummyEvent::{}(event) => BorshSerialize::serialize(event, writer),",
+            event.name
+        )
+        .unwrap();
+    }
+    writeln!(code, "        }}\n    }}\n}}").unwrap();
+    code
+}
+
+fn screaming_snake_case(name: &str) -> String {
+    let mut result = String::new();
+    for (index, c) in name.chars().enumerate() {
+        if c.is_uppercase() && index > 0 {
+            result.push('_');
+        }
+        result.push(c.to_ascii_uppercase());
+    }
+    result
+}
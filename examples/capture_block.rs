@@ -0,0 +1,295 @@
+//! Converts the response of the `getBlock` RPC method into a protobuf encoded
+//! `sf.solana.type.v1.Block`, the message the substreams receive, to record
+//! a block of the chain in `testdata/blocks/mainnet`.
+//!
+//! Usage: cargo run --features fixtures --example capture_block <slot> <response.json> <block.binpb>
+//!
+//! `make capture-block SLOT=<slot> NAME=<name>` fetches the response from
+//! `$(RPC_URL)` and writes `testdata/blocks/mainnet/<name>.binpb`. The
+//! request must ask for the `json` encoding and full transaction details:
+//!
+//! {"jsonrpc": "2.0", "id": 1, "method": "getBlock", "params": [<slot>,
+//! {"encoding": "json", "transactionDetails": "full", "rewards": false,
+//! "maxSupportedTransactionVersion": 0}]}
+//!
+//! Balances and rewards are left out, the modules do not read them. Then
+//! update the snapshots, see `tests/golden.rs`.
+
+use anyhow::{bail, Context, Result};
+use gummy_staking::fixtures::{instruction_error, transaction_error};
+use prost::Message as _;
+use serde_json::Value;
+use substreams_solana::pb::sf::solana::r#type::v1::{
+    Block, BlockHeight, CompiledInstruction, ConfirmedTransaction, InnerInstruction,
+    InnerInstructions, Message, MessageAddressTableLookup, MessageHeader, ReturnData, TokenBalance,
+    Transaction, TransactionError, TransactionStatusMeta, UiTokenAmount, UnixTimestamp,
+};
+
+fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let [slot, input, output] = args.as_slice() else {
+        bail!("usage: capture_block <slot> <response.json> <block.binpb>");
+    };
+    let slot: u64 = slot.parse().context("parsing the slot")?;
+    let response: Value = serde_json::from_str(
+        &std::fs::read_to_string(input).with_context(|| format!("reading {input}"))?,
+    )
+    .with_context(|| format!("parsing {input}"))?;
+    if let Some(error) = response.get("error") {
+        bail!("{input}: the RPC node answered {error}");
+    }
+
+    let block = block(slot, &response["result"]).with_context(|| format!("converting {input}"))?;
+    std::fs::write(output, block.encode_to_vec()).with_context(|| format!("writing {output}"))?;
+    eprintln!(
+        "wrote {output}: slot {slot}, {} transactions",
+        block.transactions.len()
+    );
+    Ok(())
+}
+
+fn block(slot: u64, block: &Value) -> Result<Block> {
+    Ok(Block {
+        previous_blockhash: string(&block["previousBlockhash"])?,
+        blockhash: string(&block["blockhash"])?,
+        parent_slot: integer(&block["parentSlot"])?,
+        transactions: array(&block["transactions"])?
+            .iter()
+            .enumerate()
+            .map(|(index, transaction)| {
+                confirmed_transaction(transaction).with_context(|| format!("transaction {index}"))
+            })
+            .collect::<Result<_>>()?,
+        rewards: Vec::new(),
+        block_time: block["blockTime"]
+            .as_i64()
+            .map(|timestamp| UnixTimestamp { timestamp }),
+        block_height: block["blockHeight"]
+            .as_u64()
+            .map(|block_height| BlockHeight { block_height }),
+        slot,
+    })
+}
+
+fn confirmed_transaction(transaction: &Value) -> Result<ConfirmedTransaction> {
+    let message = &transaction["transaction"]["message"];
+    let header = &message["header"];
+    let meta = &transaction["meta"];
+    Ok(ConfirmedTransaction {
+        transaction: Some(Transaction {
+            signatures: array(&transaction["transaction"]["signatures"])?
+                .iter()
+                .map(base58)
+                .collect::<Result<_>>()?,
+            message: Some(Message {
+                header: Some(MessageHeader {
+                    num_required_signatures: integer(&header["numRequiredSignatures"])?,
+                    num_readonly_signed_accounts: integer(&header["numReadonlySignedAccounts"])?,
+                    num_readonly_unsigned_accounts: integer(
+                        &header["numReadonlyUnsignedAccounts"],
+                    )?,
+                }),
+                account_keys: array(&message["accountKeys"])?
+                    .iter()
+                    .map(base58)
+                    .collect::<Result<_>>()?,
+                recent_blockhash: base58(&message["recentBlockhash"])?,
+                instructions: array(&message["instructions"])?
+                    .iter()
+                    .map(|instruction| {
+                        Ok(CompiledInstruction {
+                            program_id_index: integer(&instruction["programIdIndex"])?,
+                            accounts: bytes(&instruction["accounts"])?,
+                            data: base58(&instruction["data"])?,
+                        })
+                    })
+                    .collect::<Result<_>>()?,
+                versioned: transaction["version"] != "legacy",
+                address_table_lookups: optional_array(&message["addressTableLookups"])?
+                    .iter()
+                    .map(|lookup| {
+                        Ok(MessageAddressTableLookup {
+                            account_key: base58(&lookup["accountKey"])?,
+                            writable_indexes: bytes(&lookup["writableIndexes"])?,
+                            readonly_indexes: bytes(&lookup["readonlyIndexes"])?,
+                        })
+                    })
+                    .collect::<Result<_>>()?,
+            }),
+        }),
+        meta: Some(transaction_status_meta(meta)?),
+    })
+}
+
+fn transaction_status_meta(meta: &Value) -> Result<TransactionStatusMeta> {
+    let err = match &meta["err"] {
+        Value::Null => None,
+        err => Some(TransactionError {
+            err: encode_transaction_error(err)
+                .with_context(|| format!("unknown transaction error {err}"))?,
+        }),
+    };
+    let loaded_addresses = |kind| -> Result<Vec<Vec<u8>>> {
+        optional_array(&meta["loadedAddresses"][kind])?
+            .iter()
+            .map(base58)
+            .collect()
+    };
+    let token_balances = |kind| -> Result<Vec<TokenBalance>> {
+        optional_array(&meta[kind])?
+            .iter()
+            .map(token_balance)
+            .collect()
+    };
+    Ok(TransactionStatusMeta {
+        err,
+        fee: integer(&meta["fee"])?,
+        inner_instructions: optional_array(&meta["innerInstructions"])?
+            .iter()
+            .map(|inner| {
+                Ok(InnerInstructions {
+                    index: integer(&inner["index"])?,
+                    instructions: array(&inner["instructions"])?
+                        .iter()
+                        .map(|instruction| {
+                            Ok(InnerInstruction {
+                                program_id_index: integer(&instruction["programIdIndex"])?,
+                                accounts: bytes(&instruction["accounts"])?,
+                                data: base58(&instruction["data"])?,
+                                stack_height: instruction["stackHeight"]
+                                    .as_u64()
+                                    .map(|height| height as u32),
+                            })
+                        })
+                        .collect::<Result<_>>()?,
+                })
+            })
+            .collect::<Result<_>>()?,
+        inner_instructions_none: meta["innerInstructions"].is_null(),
+        pre_token_balances: token_balances("preTokenBalances")?,
+        post_token_balances: token_balances("postTokenBalances")?,
+        log_messages: optional_array(&meta["logMessages"])?
+            .iter()
+            .map(string)
+            .collect::<Result<_>>()?,
+        log_messages_none: meta["logMessages"].is_null(),
+        loaded_writable_addresses: loaded_addresses("writable")?,
+        loaded_readonly_addresses: loaded_addresses("readonly")?,
+        return_data: match &meta["returnData"] {
+            Value::Null => None,
+            return_data => Some(ReturnData {
+                program_id: base58(&return_data["programId"])?,
+                data: base64(&return_data["data"][0])?,
+            }),
+        },
+        return_data_none: meta["returnData"].is_null(),
+        compute_units_consumed: meta["computeUnitsConsumed"].as_u64(),
+        ..Default::default()
+    })
+}
+
+fn token_balance(balance: &Value) -> Result<TokenBalance> {
+    let amount = &balance["uiTokenAmount"];
+    Ok(TokenBalance {
+        account_index: integer(&balance["accountIndex"])?,
+        mint: string(&balance["mint"])?,
+        ui_token_amount: Some(UiTokenAmount {
+            ui_amount: amount["uiAmount"].as_f64().unwrap_or_default(),
+            decimals: integer(&amount["decimals"])?,
+            amount: string(&amount["amount"])?,
+            ui_amount_string: string(&amount["uiAmountString"])?,
+        }),
+        // Missing from blocks older than the fields.
+        owner: balance["owner"].as_str().unwrap_or_default().to_string(),
+        program_id: balance["programId"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string(),
+    })
+}
+
+/// The bincode encoding of an error the RPC node renders as JSON, e.g.
+/// `{"InstructionError": [1, {"Custom": 6001}]}`.
+fn encode_transaction_error(err: &Value) -> Option<Vec<u8>> {
+    if let Some(name) = err.as_str() {
+        return transaction_error(name, &[]);
+    }
+    let (name, fields) = err.as_object()?.iter().next()?;
+    let fields = match (name.as_str(), fields) {
+        ("InstructionError", Value::Array(fields)) => {
+            let index = u8::try_from(fields.first()?.as_u64()?).ok()?;
+            encode_instruction_error(index, fields.get(1)?)?
+        }
+        // `DuplicateInstruction(u8)` and the variants holding an
+        // `account_index: u8`.
+        (_, Value::Number(index)) => vec![u8::try_from(index.as_u64()?).ok()?],
+        (_, Value::Object(fields)) => {
+            vec![u8::try_from(fields.get("account_index")?.as_u64()?).ok()?]
+        }
+        _ => return None,
+    };
+    transaction_error(name, &fields)
+}
+
+fn encode_instruction_error(index: u8, err: &Value) -> Option<Vec<u8>> {
+    if let Some(name) = err.as_str() {
+        return instruction_error(index, name, &[]);
+    }
+    let (name, fields) = err.as_object()?.iter().next()?;
+    let fields = match name.as_str() {
+        "Custom" => u32::try_from(fields.as_u64()?).ok()?.to_le_bytes().to_vec(),
+        "BorshIoError" => {
+            let message = fields.as_str()?.as_bytes();
+            let mut fields = (message.len() as u64).to_le_bytes().to_vec();
+            fields.extend(message);
+            fields
+        }
+        _ => return None,
+    };
+    instruction_error(index, name, &fields)
+}
+
+fn array(value: &Value) -> Result<&Vec<Value>> {
+    value
+        .as_array()
+        .with_context(|| format!("expected an array, got {value}"))
+}
+
+/// Arrays the RPC node leaves out of older blocks.
+fn optional_array(value: &Value) -> Result<&[Value]> {
+    match value {
+        Value::Null => Ok(&[]),
+        value => Ok(array(value)?),
+    }
+}
+
+fn string(value: &Value) -> Result<String> {
+    value
+        .as_str()
+        .map(str::to_string)
+        .with_context(|| format!("expected a string, got {value}"))
+}
+
+fn integer<T: TryFrom<u64>>(value: &Value) -> Result<T> {
+    value
+        .as_u64()
+        .and_then(|value| T::try_from(value).ok())
+        .with_context(|| format!("expected an integer, got {value}"))
+}
+
+fn bytes(value: &Value) -> Result<Vec<u8>> {
+    array(value)?.iter().map(integer).collect()
+}
+
+fn base58(value: &Value) -> Result<Vec<u8>> {
+    bs58::decode(string(value)?)
+        .into_vec()
+        .with_context(|| format!("expected base58, got {value}"))
+}
+
+fn base64(value: &Value) -> Result<Vec<u8>> {
+    use base64::prelude::*;
+    BASE64_STANDARD
+        .decode(string(value)?)
+        .with_context(|| format!("expected base64, got {value}"))
+}
@@ -0,0 +1,196 @@
+//! Writes the synthetic blocks of `testdata/blocks/synthetic`, built with the
+//! fixture builders, as protobuf encoded `sf.solana.type.v1.Block` messages.
+//! They cover cases the recorded blocks of the chain do not.
+//!
+//! Usage: cargo run --features fixtures --example make_fixtures
+//!
+//! Then update the snapshots, see `tests/golden.rs`.
+
+use anyhow::{Context, Result};
+use gummy_staking::events::{
+    AdminDeleteShortReferrer, AdminEmergencyWithdraw, AdminRegisterShortReferrer, Deposit,
+    GummyEvent, Pubkey, RegisterShortReferrer, SetReferrer, Withdraw,
+};
+use gummy_staking::fixtures::{self, program_data_line, program_data_line_from_bytes};
+use gummy_staking::fixtures::{TransactionBuilder, PROGRAM_ID};
+use prost::Message;
+use sha2::{Digest, Sha256};
+use substreams_solana::pb::sf::solana::r#type::v1::Block;
+
+/// A program routing calls to the staking program.
+const ROUTER: Pubkey = Pubkey([9; 32]);
+const USER: Pubkey = Pubkey([2; 32]);
+const REFERRER: Pubkey = Pubkey([3; 32]);
+const NEW_REFERRER: Pubkey = Pubkey([4; 32]);
+const ADMIN: Pubkey = Pubkey([5; 32]);
+
+const SLOT: u64 = 264062815;
+const TIMESTAMP: i64 = 1_717_000_000;
+
+fn main() -> Result<()> {
+    let program_id: Pubkey = PROGRAM_ID.parse().unwrap();
+    let blocks = [
+        ("all_events", all_events(&program_id)),
+        ("emergency_withdraw", emergency_withdraw(&program_id)),
+        ("failed_transaction", failed_transaction(&program_id)),
+        ("cpi_nested", cpi_nested(&program_id)),
+        ("malformed_payloads", malformed_payloads(&program_id)),
+        ("truncated_logs", truncated_logs(&program_id)),
+    ];
+    for (name, block) in blocks {
+        let path = format!("testdata/blocks/synthetic/{name}.binpb");
+        std::fs::write(&path, block.encode_to_vec()).with_context(|| format!("writing {path}"))?;
+        eprintln!("wrote {path}");
+    }
+    Ok(())
+}
+
+fn deposit(amount: u64, total_amount: u64) -> GummyEvent {
+    GummyEvent::Deposit(Deposit {
+        user: USER,
+        amount,
+        total_amount,
+        lock_expires: 1_720_000_000,
+        referrer: REFERRER,
+    })
+}
+
+/// A signature unique to each transaction of the fixtures.
+fn signature(block: u8, transaction: u8) -> [u8; 64] {
+    let mut signature = [block; 64];
+    signature[63] = transaction;
+    signature
+}
+
+/// One transaction per event of the program, each logging its event.
+fn all_events(program_id: &Pubkey) -> Block {
+    let events = [
+        deposit(100, 100),
+        GummyEvent::Withdraw(Withdraw {
+            user: USER,
+            total_amount: 40,
+        }),
+        GummyEvent::SetReferrer(SetReferrer {
+            user: USER,
+            old_referrer: REFERRER,
+            new_referrer: NEW_REFERRER,
+        }),
+        GummyEvent::RegisterShortReferrer(RegisterShortReferrer {
+            full: NEW_REFERRER,
+            short: b"gummy".to_vec(),
+        }),
+        GummyEvent::AdminRegisterShortReferrer(AdminRegisterShortReferrer {
+            full: REFERRER,
+            short: b"Bear".to_vec(),
+            initiator: ADMIN,
+        }),
+        GummyEvent::AdminDeleteShortReferrer(AdminDeleteShortReferrer {
+            short: b"gummy".to_vec(),
+            initiator: ADMIN,
+        }),
+        GummyEvent::AdminEmergencyWithdraw(AdminEmergencyWithdraw {
+            user: USER,
+            total_amount: 0,
+            initiator: ADMIN,
+        }),
+    ];
+    let transactions = events
+        .iter()
+        .enumerate()
+        .map(|(index, event)| {
+            TransactionBuilder::new(signature(1, index as u8))
+                .instruction(program_id, &[program_data_line(event)])
+                .build()
+        })
+        .collect();
+    fixtures::block(SLOT, TIMESTAMP, transactions)
+}
+
+/// A deposit, then an admin withdrawing the whole position in the same
+/// transaction.
+fn emergency_withdraw(program_id: &Pubkey) -> Block {
+    let withdraw = GummyEvent::AdminEmergencyWithdraw(AdminEmergencyWithdraw {
+        user: USER,
+        total_amount: 0,
+        initiator: ADMIN,
+    });
+    let transaction = TransactionBuilder::new(signature(2, 0))
+        .instruction(program_id, &[program_data_line(&deposit(250, 250))])
+        .instruction(program_id, &[program_data_line(&withdraw)])
+        .build();
+    fixtures::block(SLOT + 1, TIMESTAMP + 1, vec![transaction])
+}
+
+/// A successful deposit followed by one rejected by the program.
+fn failed_transaction(program_id: &Pubkey) -> Block {
+    let transactions = vec![
+        TransactionBuilder::new(signature(3, 0))
+            .instruction(program_id, &[program_data_line(&deposit(10, 10))])
+            .build(),
+        TransactionBuilder::new(signature(3, 1))
+            .instruction(program_id, &[program_data_line(&deposit(20, 30))])
+            .failed(6001)
+            .build(),
+    ];
+    fixtures::block(SLOT + 2, TIMESTAMP + 2, transactions)
+}
+
+/// The program called through a router, logging its event, and events
+/// emitted with `emit_cpi!`.
+fn cpi_nested(program_id: &Pubkey) -> Block {
+    let set_referrer = GummyEvent::SetReferrer(SetReferrer {
+        user: USER,
+        old_referrer: REFERRER,
+        new_referrer: NEW_REFERRER,
+    });
+    let withdraw = GummyEvent::Withdraw(Withdraw {
+        user: USER,
+        total_amount: 5,
+    });
+    let transaction = TransactionBuilder::new(signature(4, 0))
+        .cpi(&ROUTER, program_id, &[program_data_line(&deposit(5, 10))])
+        .emit_cpi(program_id, &[set_referrer, withdraw])
+        .build();
+    fixtures::block(SLOT + 3, TIMESTAMP + 3, vec![transaction])
+}
+
+/// Payloads the decoder rejects: a truncated discriminator, an unknown one,
+/// a truncated event and an event followed by extra bytes.
+fn malformed_payloads(program_id: &Pubkey) -> Block {
+    let deposit = borsh::to_vec(&deposit(1, 1)).unwrap();
+    let mut trailing = deposit.clone();
+    trailing.extend([0, 1, 2]);
+    let lines = [
+        program_data_line_from_bytes(&deposit[..5]),
+        program_data_line_from_bytes(&[0xff; 16]),
+        program_data_line_from_bytes(&deposit[..40]),
+        program_data_line_from_bytes(&trailing),
+        "Program data: not base64!".to_string(),
+    ];
+    let transaction = TransactionBuilder::new(signature(5, 0))
+        .instruction(program_id, &lines)
+        .build();
+    fixtures::block(SLOT + 4, TIMESTAMP + 4, vec![transaction])
+}
+
+/// A transaction whose logs were cut while the program ran its second
+/// instruction: the event it emitted with `emit_cpi!` is recovered from the
+/// inner instructions, the instruction itself is reported as unrecovered.
+fn truncated_logs(program_id: &Pubkey) -> Block {
+    let set_referrer = GummyEvent::SetReferrer(SetReferrer {
+        user: USER,
+        old_referrer: NEW_REFERRER,
+        new_referrer: REFERRER,
+    });
+    // The Anchor discriminator of the `set_referrer` instruction.
+    let instruction = &Sha256::digest("global:set_referrer")[..8];
+    let transaction = TransactionBuilder::new(signature(6, 0))
+        .instruction(program_id, &[program_data_line(&deposit(5, 15))])
+        .emit_cpi(program_id, &[set_referrer])
+        .data(instruction)
+        // The four lines of the first instruction, then the invocation of the
+        // second.
+        .truncate_logs(5)
+        .build();
+    fixtures::block(SLOT + 5, TIMESTAMP + 5, vec![transaction])
+}
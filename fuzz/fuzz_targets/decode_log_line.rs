@@ -0,0 +1,21 @@
+//! Decodes arbitrary `Program data:` payloads, as found in the logs.
+//!
+//! Usage: cargo +nightly fuzz run decode_log_line
+
+#![no_main]
+
+use base64::prelude::*;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // Payloads that are not base64.
+    if let Ok(message) = std::str::from_utf8(data) {
+        let _ = gummy_staking::decode_program_data(message);
+    }
+
+    // Any event that decodes is made of all the payload, so it serializes
+    // back to it.
+    if let Ok(event) = gummy_staking::decode_program_data(&BASE64_STANDARD.encode(data)) {
+        assert_eq!(borsh::to_vec(&event).unwrap(), data);
+    }
+});
@@ -0,0 +1,334 @@
+//! Runs sequences of blocks of arbitrary transactions through the modules up
+//! to `map_events`, stores included, so that state carries over from one block
+//! to the next. Transactions are generated from a few building blocks, valid
+//! or not, so the fuzzer reaches the decoders and the tables rather than
+//! stopping at the first malformed field. Events of a few users, referrers
+//! and short codes make blocks update what earlier ones stored.
+//!
+//! Usage: cargo +nightly fuzz run process_transaction
+
+#![no_main]
+
+use arbitrary::Arbitrary;
+use gummy_staking::events::{
+    AdminDeleteShortReferrer, AdminEmergencyWithdraw, AdminRegisterShortReferrer, Deposit,
+    GummyEvent, Pubkey, RegisterShortReferrer, SetReferrer, Withdraw, EVENTS, EVENT_IX_TAG_LE,
+};
+use gummy_staking::fixtures::{self, program_data_line_from_bytes, FEE_PAYER, PROGRAM_ID};
+use gummy_staking::NativeModules;
+use libfuzzer_sys::fuzz_target;
+use substreams_solana::pb::sf::solana::r#type::v1::{
+    Block, CompiledInstruction, ConfirmedTransaction, InnerInstruction, InnerInstructions, Message,
+    Transaction, TransactionError, TransactionStatusMeta, UnixTimestamp,
+};
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    lenient_decoding: bool,
+    record_failed_transactions: bool,
+    blocks: Vec<FuzzBlock>,
+}
+
+#[derive(Arbitrary, Debug)]
+struct FuzzBlock {
+    slot: u64,
+    timestamp: Option<i64>,
+    transactions: Vec<FuzzTransaction>,
+}
+
+#[derive(Arbitrary, Debug)]
+struct FuzzTransaction {
+    signatures: Vec<Vec<u8>>,
+    account_keys: Vec<Account>,
+    loaded_addresses: Vec<Account>,
+    instructions: Vec<Instruction>,
+    inner_instructions: Vec<(u32, Vec<Instruction>)>,
+    log_messages: Vec<LogLine>,
+    err: Option<Vec<u8>>,
+}
+
+#[derive(Arbitrary, Debug)]
+enum Account {
+    Program,
+    FeePayer,
+    Other(Vec<u8>),
+}
+
+#[derive(Arbitrary, Debug)]
+struct Instruction {
+    program_id_index: u32,
+    /// Prefixed with the tag of `emit_cpi!` self-invocations.
+    tagged: bool,
+    data: Payload,
+}
+
+#[derive(Arbitrary, Debug)]
+enum Payload {
+    /// An event of the IDL, picked by `index`, followed by arbitrary bytes.
+    Event {
+        index: u8,
+        data: Vec<u8>,
+    },
+    /// A well formed event, see `KnownEvent`.
+    Known(KnownEvent),
+    Raw(Vec<u8>),
+}
+
+/// An event between a few accounts and short codes, each picked by an index.
+#[derive(Arbitrary, Debug)]
+enum KnownEvent {
+    Deposit {
+        user: u8,
+        amount: u64,
+        total_amount: u64,
+        lock_expires: u32,
+        referrer: u8,
+    },
+    Withdraw {
+        user: u8,
+        total_amount: u64,
+    },
+    SetReferrer {
+        user: u8,
+        old_referrer: u8,
+        new_referrer: u8,
+    },
+    RegisterShortReferrer {
+        full: u8,
+        short: u8,
+    },
+    AdminRegisterShortReferrer {
+        full: u8,
+        short: u8,
+    },
+    AdminDeleteShortReferrer {
+        short: u8,
+    },
+    AdminEmergencyWithdraw {
+        user: u8,
+        total_amount: u64,
+    },
+}
+
+#[derive(Arbitrary, Debug)]
+enum LogLine {
+    Invoke { program: Account, depth: u8 },
+    Success(Account),
+    Failed(Account),
+    Data(Payload),
+    Truncated,
+    Raw(String),
+}
+
+impl Account {
+    fn bytes(&self, program_id: &Pubkey) -> Vec<u8> {
+        match self {
+            Account::Program => program_id.0.to_vec(),
+            Account::FeePayer => FEE_PAYER.0.to_vec(),
+            Account::Other(bytes) => bytes.clone(),
+        }
+    }
+
+    fn address(&self, program_id: &Pubkey) -> String {
+        match self {
+            Account::Program => program_id.to_string(),
+            Account::FeePayer => FEE_PAYER.to_string(),
+            Account::Other(bytes) => bs58::encode(bytes).into_string(),
+        }
+    }
+}
+
+impl Instruction {
+    fn data(&self) -> Vec<u8> {
+        let mut data = if self.tagged {
+            EVENT_IX_TAG_LE.to_vec()
+        } else {
+            Vec::new()
+        };
+        data.extend(self.data.bytes());
+        data
+    }
+}
+
+impl Payload {
+    fn bytes(&self) -> Vec<u8> {
+        match self {
+            Payload::Event { index, data } => {
+                let event = &EVENTS[*index as usize % EVENTS.len()];
+                let mut bytes = event.discriminator.to_vec();
+                bytes.extend(data);
+                bytes
+            }
+            Payload::Known(event) => borsh::to_vec(&event.event()).unwrap(),
+            Payload::Raw(bytes) => bytes.clone(),
+        }
+    }
+}
+
+/// Short codes differing by case or normalization only, as well as invalid
+/// ones.
+const SHORT_CODES: &[&[u8]] = &[
+    b"gummy",
+    b"GUMMY",
+    b"bear",
+    "bear\u{0301}".as_bytes(),
+    b"\xff",
+];
+
+impl KnownEvent {
+    fn event(&self) -> GummyEvent {
+        let account = |index: &u8| Pubkey([index % 4 + 2; 32]);
+        let short = |index: &u8| SHORT_CODES[*index as usize % SHORT_CODES.len()].to_vec();
+        let admin = Pubkey([9; 32]);
+        match self {
+            KnownEvent::Deposit {
+                user,
+                amount,
+                total_amount,
+                lock_expires,
+                referrer,
+            } => GummyEvent::Deposit(Deposit {
+                user: account(user),
+                amount: *amount,
+                total_amount: *total_amount,
+                lock_expires: *lock_expires,
+                referrer: account(referrer),
+            }),
+            KnownEvent::Withdraw { user, total_amount } => GummyEvent::Withdraw(Withdraw {
+                user: account(user),
+                total_amount: *total_amount,
+            }),
+            KnownEvent::SetReferrer {
+                user,
+                old_referrer,
+                new_referrer,
+            } => GummyEvent::SetReferrer(SetReferrer {
+                user: account(user),
+                old_referrer: account(old_referrer),
+                new_referrer: account(new_referrer),
+            }),
+            KnownEvent::RegisterShortReferrer { full, short: code } => {
+                GummyEvent::RegisterShortReferrer(RegisterShortReferrer {
+                    full: account(full),
+                    short: short(code),
+                })
+            }
+            KnownEvent::AdminRegisterShortReferrer { full, short: code } => {
+                GummyEvent::AdminRegisterShortReferrer(AdminRegisterShortReferrer {
+                    full: account(full),
+                    short: short(code),
+                    initiator: admin,
+                })
+            }
+            KnownEvent::AdminDeleteShortReferrer { short: code } => {
+                GummyEvent::AdminDeleteShortReferrer(AdminDeleteShortReferrer {
+                    short: short(code),
+                    initiator: admin,
+                })
+            }
+            KnownEvent::AdminEmergencyWithdraw { user, total_amount } => {
+                GummyEvent::AdminEmergencyWithdraw(AdminEmergencyWithdraw {
+                    user: account(user),
+                    total_amount: *total_amount,
+                    initiator: admin,
+                })
+            }
+        }
+    }
+}
+
+impl LogLine {
+    fn to_string(&self, program_id: &Pubkey) -> String {
+        match self {
+            LogLine::Invoke { program, depth } => {
+                format!("Program {} invoke [{depth}]", program.address(program_id))
+            }
+            LogLine::Success(program) => format!("Program {} success", program.address(program_id)),
+            LogLine::Failed(program) => format!(
+                "Program {} failed: custom program error: 0x1771",
+                program.address(program_id)
+            ),
+            LogLine::Data(payload) => program_data_line_from_bytes(&payload.bytes()),
+            LogLine::Truncated => "Log truncated".to_string(),
+            LogLine::Raw(line) => line.clone(),
+        }
+    }
+}
+
+impl FuzzTransaction {
+    fn build(&self, program_id: &Pubkey) -> ConfirmedTransaction {
+        let compile = |instruction: &Instruction| CompiledInstruction {
+            program_id_index: instruction.program_id_index,
+            accounts: vec![0],
+            data: instruction.data(),
+        };
+        ConfirmedTransaction {
+            transaction: Some(Transaction {
+                signatures: self.signatures.clone(),
+                message: Some(Message {
+                    account_keys: self
+                        .account_keys
+                        .iter()
+                        .map(|account| account.bytes(program_id))
+                        .collect(),
+                    instructions: self.instructions.iter().map(compile).collect(),
+                    ..Default::default()
+                }),
+            }),
+            meta: Some(TransactionStatusMeta {
+                err: self.err.clone().map(|err| TransactionError { err }),
+                inner_instructions: self
+                    .inner_instructions
+                    .iter()
+                    .map(|(index, instructions)| InnerInstructions {
+                        index: *index,
+                        instructions: instructions
+                            .iter()
+                            .map(|instruction| InnerInstruction {
+                                program_id_index: instruction.program_id_index,
+                                accounts: vec![0],
+                                data: instruction.data(),
+                                stack_height: Some(2),
+                            })
+                            .collect(),
+                    })
+                    .collect(),
+                log_messages: self
+                    .log_messages
+                    .iter()
+                    .map(|line| line.to_string(program_id))
+                    .collect(),
+                loaded_writable_addresses: self
+                    .loaded_addresses
+                    .iter()
+                    .map(|account| account.bytes(program_id))
+                    .collect(),
+                ..Default::default()
+            }),
+        }
+    }
+}
+
+fuzz_target!(|input: Input| {
+    let program_id: Pubkey = PROGRAM_ID.parse().unwrap();
+    let params = format!(
+        "program_ids={PROGRAM_ID}&lenient_decoding={}&record_failed_transactions={}",
+        input.lenient_decoding, input.record_failed_transactions
+    );
+    let mut modules = NativeModules::new(&params);
+    for fuzz_block in &input.blocks {
+        let mut block: Block = fixtures::block(
+            fuzz_block.slot,
+            0,
+            fuzz_block
+                .transactions
+                .iter()
+                .map(|transaction| transaction.build(&program_id))
+                .collect(),
+        );
+        block.block_time = fuzz_block
+            .timestamp
+            .map(|timestamp| UnixTimestamp { timestamp });
+        modules.process(&block).unwrap();
+    }
+});
@@ -0,0 +1,233 @@
+CREATE TABLE IF NOT EXISTS DepositEvent (
+    id String,
+    program_id String,
+    timestamp Nullable(Int64),
+    user String,
+    amount UInt64,
+    total_amount UInt64,
+    lock_expires UInt32,
+    referrer String
+) ENGINE = ReplacingMergeTree()
+ORDER BY (id);
+
+CREATE TABLE IF NOT EXISTS WithdrawEvent (
+    id String,
+    program_id String,
+    timestamp Nullable(Int64),
+    user String,
+    total_amount UInt64,
+    -- From the previous staked total, or else from the token balances of the
+    -- transaction, unset when neither tells
+    withdrawn_amount Nullable(UInt64),
+    -- Set for withdrawals forced by an admin, also listed in AdminAction
+    emergency Bool,
+    -- The admin, for emergency withdrawals
+    initiator Nullable(String)
+) ENGINE = ReplacingMergeTree()
+ORDER BY (id);
+
+-- Keyed by program id and user
+CREATE TABLE IF NOT EXISTS Referrer (
+    id String,
+    program_id String,
+    user String,
+    referrer String
+) ENGINE = ReplacingMergeTree()
+ORDER BY (id);
+
+CREATE TABLE IF NOT EXISTS ReferrerChange (
+    id String,
+    program_id String,
+    user String,
+    old_referrer String,
+    new_referrer String,
+    slot UInt64,
+    timestamp Nullable(Int64),
+    tx_signature String,
+    -- The referrer indexed before this change, unset when none was seen
+    stored_referrer Nullable(String),
+    -- Set when old_referrer differs from stored_referrer, hinting at a missed event
+    old_referrer_mismatch Nullable(Bool)
+) ENGINE = ReplacingMergeTree()
+ORDER BY (id);
+
+-- Keyed by program id and hex encoded short code
+CREATE TABLE IF NOT EXISTS ShortReferrer (
+    id String,
+    program_id String,
+    -- Case folded display form, see short_status
+    short String,
+    short_hex String,
+    short_base64 String,
+    -- 'valid', 'invalid_utf8' or 'ambiguous' when a different code was
+    -- registered with the same display form before
+    short_status String,
+    full String
+) ENGINE = ReplacingMergeTree()
+ORDER BY (id);
+
+CREATE TABLE IF NOT EXISTS ShortReferrerRegistration (
+    id String,
+    program_id String,
+    action String,
+    short String,
+    short_hex String,
+    short_base64 String,
+    short_status String,
+    -- The address registered, or the one a deleted code pointed to if known
+    full Nullable(String),
+    -- The address a re-registered code pointed to before
+    previous_full Nullable(String),
+    -- The admin who made the change, unset for registrations by the user
+    initiator Nullable(String),
+    admin Bool,
+    slot UInt64,
+    timestamp Nullable(Int64),
+    tx_signature String
+) ENGINE = ReplacingMergeTree()
+ORDER BY (id);
+
+-- Keyed by program id, full address and hex encoded short code
+CREATE TABLE IF NOT EXISTS ReferrerShortCode (
+    id String,
+    program_id String,
+    full String,
+    short String,
+    short_hex String,
+    short_base64 String,
+    short_status String,
+    active Bool,
+    -- Slot of the last registration of the code to this address
+    registered_slot UInt64,
+    -- Slot the code last stopped pointing to this address, kept when it is
+    -- registered to it again
+    removed_slot Nullable(UInt64)
+) ENGINE = ReplacingMergeTree()
+ORDER BY (id);
+
+-- Privileged actions, for auditing. Targets either a user or a short code.
+CREATE TABLE IF NOT EXISTS AdminAction (
+    id String,
+    program_id String,
+    action String,
+    initiator String,
+    user Nullable(String),
+    short Nullable(String),
+    short_hex Nullable(String),
+    short_base64 Nullable(String),
+    short_status Nullable(String),
+    amount Nullable(UInt64),
+    slot UInt64,
+    tx_signature String,
+    timestamp Nullable(Int64)
+) ENGINE = ReplacingMergeTree()
+ORDER BY (id);
+
+CREATE TABLE IF NOT EXISTS FailedAttempt (
+    id String,
+    program_id String,
+    timestamp Nullable(Int64),
+    event String,
+    user Nullable(String),
+    error String
+) ENGINE = ReplacingMergeTree()
+ORDER BY (id);
+
+-- Keyed by program id and transaction signature
+CREATE TABLE IF NOT EXISTS TruncatedTransaction (
+    id String,
+    program_id String,
+    tx_signature String,
+    slot UInt64,
+    timestamp Nullable(Int64),
+    log_index UInt32,
+    unlogged_invocations UInt32,
+    recovered_events UInt32,
+    unrecovered_instructions String
+) ENGINE = ReplacingMergeTree()
+ORDER BY (id);
+
+-- Keyed by program id and user
+CREATE TABLE IF NOT EXISTS UserPosition (
+    id String,
+    program_id String,
+    user String,
+    total_amount UInt64,
+    lock_expires UInt32,
+    first_deposit_slot Nullable(UInt64),
+    last_activity_slot UInt64,
+    deposit_count UInt64
+) ENGINE = ReplacingMergeTree()
+ORDER BY (id);
+
+-- Keyed by program id and slot
+CREATE TABLE IF NOT EXISTS TotalValueLocked (
+    id String,
+    program_id String,
+    slot UInt64,
+    timestamp Nullable(Int64),
+    tvl UInt128
+) ENGINE = ReplacingMergeTree()
+ORDER BY (id);
+
+-- Keyed by program id and start of the UTC day
+CREATE TABLE IF NOT EXISTS DailyTotalValueLocked (
+    id String,
+    program_id String,
+    day_start Int64,
+    open UInt128,
+    high UInt128,
+    low UInt128,
+    close UInt128
+) ENGINE = ReplacingMergeTree()
+ORDER BY (id);
+
+CREATE TABLE IF NOT EXISTS Error (
+    id String,
+    program_id String,
+    description String,
+    -- BASE64_DECODE, TOO_SHORT, UNKNOWN_DISCRIMINATOR, BORSH_DECODE or TRAILING_BYTES
+    kind LowCardinality(String),
+    event Nullable(String),
+    discriminator Nullable(String),
+    message Nullable(String),
+    trailing_bytes UInt32,
+    slot UInt64,
+    tx_signature String,
+    timestamp Nullable(Int64),
+    -- LOG or INNER_INSTRUCTION, which of the position columns below apply
+    source LowCardinality(String),
+    log_index UInt32,
+    instruction_index UInt32,
+    inner_instruction_index UInt32,
+    -- Base64 encoded
+    payload String
+) ENGINE = ReplacingMergeTree()
+ORDER BY (id);
+
+-- Events decoded in lenient mode despite bytes past the end of their layout
+CREATE TABLE IF NOT EXISTS IgnoredTrailingBytes (
+    id String,
+    program_id String,
+    event String,
+    layout_version UInt32,
+    ignored_bytes UInt32,
+    slot UInt64,
+    tx_signature String
+) ENGINE = ReplacingMergeTree()
+ORDER BY (id);
+
+-- What the replay tool needs to decode the payload of an Error row again,
+-- under the id of that row. Rows recovered by a replay are deleted along with
+-- their Error row.
+CREATE TABLE IF NOT EXISTS Quarantine (
+    id String,
+    tx_index UInt32,
+    -- Payloads of failed transactions are replayed as FailedAttempt rows
+    tx_failed Bool,
+    tx_error Nullable(String)
+) ENGINE = ReplacingMergeTree()
+ORDER BY (id);
+
+-- Tables of the IDL events decoded into IdlEvent, generated by src/schema.rs.
+-- End of the IDL event tables.
@@ -0,0 +1,225 @@
+CREATE TABLE IF NOT EXISTS "DepositEvent" (
+    "id" TEXT NOT NULL PRIMARY KEY,
+    "program_id" TEXT NOT NULL,
+    "timestamp" BIGINT,
+    "user" TEXT NOT NULL,
+    "amount" NUMERIC NOT NULL,
+    "total_amount" NUMERIC NOT NULL,
+    "lock_expires" BIGINT NOT NULL,
+    "referrer" TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS "WithdrawEvent" (
+    "id" TEXT NOT NULL PRIMARY KEY,
+    "program_id" TEXT NOT NULL,
+    "timestamp" BIGINT,
+    "user" TEXT NOT NULL,
+    "total_amount" NUMERIC NOT NULL,
+    -- From the previous staked total, or else from the token balances of the
+    -- transaction, unset when neither tells
+    "withdrawn_amount" NUMERIC,
+    -- Set for withdrawals forced by an admin, also listed in AdminAction
+    "emergency" BOOLEAN NOT NULL,
+    -- The admin, for emergency withdrawals
+    "initiator" TEXT
+);
+
+-- Keyed by program id and user
+CREATE TABLE IF NOT EXISTS "Referrer" (
+    "id" TEXT NOT NULL PRIMARY KEY,
+    "program_id" TEXT NOT NULL,
+    "user" TEXT NOT NULL,
+    "referrer" TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS "ReferrerChange" (
+    "id" TEXT NOT NULL PRIMARY KEY,
+    "program_id" TEXT NOT NULL,
+    "user" TEXT NOT NULL,
+    "old_referrer" TEXT NOT NULL,
+    "new_referrer" TEXT NOT NULL,
+    "slot" NUMERIC NOT NULL,
+    "timestamp" BIGINT,
+    "tx_signature" TEXT NOT NULL,
+    -- The referrer indexed before this change, unset when none was seen
+    "stored_referrer" TEXT,
+    -- Set when old_referrer differs from stored_referrer, hinting at a missed event
+    "old_referrer_mismatch" BOOLEAN
+);
+
+-- Keyed by program id and hex encoded short code
+CREATE TABLE IF NOT EXISTS "ShortReferrer" (
+    "id" TEXT NOT NULL PRIMARY KEY,
+    "program_id" TEXT NOT NULL,
+    -- Case folded display form, see short_status
+    "short" TEXT NOT NULL,
+    "short_hex" TEXT NOT NULL,
+    "short_base64" TEXT NOT NULL,
+    -- 'valid', 'invalid_utf8' or 'ambiguous' when a different code was
+    -- registered with the same display form before
+    "short_status" TEXT NOT NULL,
+    "full" TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS "ShortReferrerRegistration" (
+    "id" TEXT NOT NULL PRIMARY KEY,
+    "program_id" TEXT NOT NULL,
+    "action" TEXT NOT NULL,
+    "short" TEXT NOT NULL,
+    "short_hex" TEXT NOT NULL,
+    "short_base64" TEXT NOT NULL,
+    "short_status" TEXT NOT NULL,
+    -- The address registered, or the one a deleted code pointed to if known
+    "full" TEXT,
+    -- The address a re-registered code pointed to before
+    "previous_full" TEXT,
+    -- The admin who made the change, unset for registrations by the user
+    "initiator" TEXT,
+    "admin" BOOLEAN NOT NULL,
+    "slot" NUMERIC NOT NULL,
+    "timestamp" BIGINT,
+    "tx_signature" TEXT NOT NULL
+);
+
+-- Keyed by program id, full address and hex encoded short code
+CREATE TABLE IF NOT EXISTS "ReferrerShortCode" (
+    "id" TEXT NOT NULL PRIMARY KEY,
+    "program_id" TEXT NOT NULL,
+    "full" TEXT NOT NULL,
+    "short" TEXT NOT NULL,
+    "short_hex" TEXT NOT NULL,
+    "short_base64" TEXT NOT NULL,
+    "short_status" TEXT NOT NULL,
+    "active" BOOLEAN NOT NULL,
+    -- Slot of the last registration of the code to this address
+    "registered_slot" NUMERIC NOT NULL,
+    -- Slot the code last stopped pointing to this address, kept when it is
+    -- registered to it again
+    "removed_slot" NUMERIC
+);
+
+-- Privileged actions, for auditing. Targets either a user or a short code.
+CREATE TABLE IF NOT EXISTS "AdminAction" (
+    "id" TEXT NOT NULL PRIMARY KEY,
+    "program_id" TEXT NOT NULL,
+    "action" TEXT NOT NULL,
+    "initiator" TEXT NOT NULL,
+    "user" TEXT,
+    "short" TEXT,
+    "short_hex" TEXT,
+    "short_base64" TEXT,
+    "short_status" TEXT,
+    "amount" NUMERIC,
+    "slot" NUMERIC NOT NULL,
+    "tx_signature" TEXT NOT NULL,
+    "timestamp" BIGINT
+);
+
+CREATE TABLE IF NOT EXISTS "FailedAttempt" (
+    "id" TEXT NOT NULL PRIMARY KEY,
+    "program_id" TEXT NOT NULL,
+    "timestamp" BIGINT,
+    "event" TEXT NOT NULL,
+    "user" TEXT,
+    "error" TEXT NOT NULL
+);
+
+-- Keyed by program id and transaction signature
+CREATE TABLE IF NOT EXISTS "TruncatedTransaction" (
+    "id" TEXT NOT NULL PRIMARY KEY,
+    "program_id" TEXT NOT NULL,
+    "tx_signature" TEXT NOT NULL,
+    "slot" NUMERIC NOT NULL,
+    "timestamp" BIGINT,
+    "log_index" BIGINT NOT NULL,
+    "unlogged_invocations" BIGINT NOT NULL,
+    "recovered_events" BIGINT NOT NULL,
+    "unrecovered_instructions" TEXT NOT NULL
+);
+
+-- Keyed by program id and user
+CREATE TABLE IF NOT EXISTS "UserPosition" (
+    "id" TEXT NOT NULL PRIMARY KEY,
+    "program_id" TEXT NOT NULL,
+    "user" TEXT NOT NULL,
+    "total_amount" NUMERIC NOT NULL,
+    "lock_expires" BIGINT NOT NULL,
+    "first_deposit_slot" NUMERIC,
+    "last_activity_slot" NUMERIC NOT NULL,
+    "deposit_count" NUMERIC NOT NULL
+);
+
+-- Keyed by program id and slot
+CREATE TABLE IF NOT EXISTS "TotalValueLocked" (
+    "id" TEXT NOT NULL PRIMARY KEY,
+    "program_id" TEXT NOT NULL,
+    "slot" NUMERIC NOT NULL,
+    "timestamp" BIGINT,
+    "tvl" NUMERIC NOT NULL
+);
+
+-- Keyed by program id and start of the UTC day
+CREATE TABLE IF NOT EXISTS "DailyTotalValueLocked" (
+    "id" TEXT NOT NULL PRIMARY KEY,
+    "program_id" TEXT NOT NULL,
+    "day_start" BIGINT NOT NULL,
+    "open" NUMERIC NOT NULL,
+    "high" NUMERIC NOT NULL,
+    "low" NUMERIC NOT NULL,
+    "close" NUMERIC NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS "Error" (
+    "id" TEXT NOT NULL PRIMARY KEY,
+    "program_id" TEXT NOT NULL,
+    "description" TEXT NOT NULL,
+    -- BASE64_DECODE, TOO_SHORT, UNKNOWN_DISCRIMINATOR, BORSH_DECODE or TRAILING_BYTES
+    "kind" TEXT NOT NULL,
+    "event" TEXT,
+    "discriminator" TEXT,
+    "message" TEXT,
+    "trailing_bytes" BIGINT NOT NULL,
+    "slot" NUMERIC NOT NULL,
+    "tx_signature" TEXT NOT NULL,
+    "timestamp" BIGINT,
+    -- LOG or INNER_INSTRUCTION, which of the position columns below apply
+    "source" TEXT NOT NULL,
+    "log_index" BIGINT NOT NULL,
+    "instruction_index" BIGINT NOT NULL,
+    "inner_instruction_index" BIGINT NOT NULL,
+    -- Base64 encoded
+    "payload" TEXT NOT NULL
+);
+
+-- Events decoded in lenient mode despite bytes past the end of their layout
+CREATE TABLE IF NOT EXISTS "IgnoredTrailingBytes" (
+    "id" TEXT NOT NULL PRIMARY KEY,
+    "program_id" TEXT NOT NULL,
+    "event" TEXT NOT NULL,
+    "layout_version" BIGINT NOT NULL,
+    "ignored_bytes" BIGINT NOT NULL,
+    "slot" NUMERIC NOT NULL,
+    "tx_signature" TEXT NOT NULL
+);
+
+-- What the replay tool needs to decode the payload of an Error row again,
+-- under the id of that row. Rows recovered by a replay are deleted along with
+-- their Error row.
+CREATE TABLE IF NOT EXISTS "Quarantine" (
+    "id" TEXT NOT NULL PRIMARY KEY,
+    "tx_index" BIGINT NOT NULL,
+    -- Payloads of failed transactions are replayed as FailedAttempt rows
+    "tx_failed" BOOLEAN NOT NULL,
+    "tx_error" TEXT
+);
+
+-- Tables of the IDL events decoded into IdlEvent, generated by src/schema.rs.
+-- End of the IDL event tables.
+
+CREATE INDEX IF NOT EXISTS "DepositEvent_user_idx" ON "DepositEvent" ("user");
+CREATE INDEX IF NOT EXISTS "WithdrawEvent_user_idx" ON "WithdrawEvent" ("user");
+CREATE INDEX IF NOT EXISTS "ReferrerChange_user_idx" ON "ReferrerChange" ("user");
+CREATE INDEX IF NOT EXISTS "ShortReferrerRegistration_short_idx" ON "ShortReferrerRegistration" ("short_hex");
+CREATE INDEX IF NOT EXISTS "ReferrerShortCode_full_idx" ON "ReferrerShortCode" ("full");
+CREATE INDEX IF NOT EXISTS "AdminAction_initiator_idx" ON "AdminAction" ("initiator");
+CREATE INDEX IF NOT EXISTS "Error_kind_idx" ON "Error" ("kind");
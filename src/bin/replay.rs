@@ -0,0 +1,82 @@
+//! Re-runs the decoders over rows exported from the `Quarantine` table,
+//! joined with their `Error` rows, and writes SQL statements adding the rows
+//! that were missed and deleting the recovered `Error` and quarantine rows.
+//! Payloads of failed transactions become `FailedAttempt` rows. `--lenient`
+//! accepts payloads longer than their layout, `--clickhouse` writes
+//! statements for ClickHouse instead of Postgres.
+//!
+//! Usage: replay [--clickhouse] [--lenient] <quarantine.tsv> <output.sql>
+//!
+//! Only history tables are written. When recovered events would have changed
+//! `Referrer`, `ShortReferrer`, `ReferrerShortCode`, `UserPosition` or the TVL
+//! tables, those are reported along with the slot to resync them from, and
+//! stay wrong until resynced. Consumers of the `EntityChanges` of `map_events`
+//! are not updated either and need a resync.
+//!
+//! With Postgres, `make replay-postgres apply-replay-postgres` runs:
+//!
+//! psql "$DSN" -c "\copy (SELECT e.program_id, e.slot, e.tx_signature,
+//! q.tx_index, e.timestamp, e.source, e.log_index, e.instruction_index,
+//! e.inner_instruction_index, e.payload, q.tx_error FROM \"Quarantine\" q
+//! JOIN \"Error\" e USING (id)) TO 'quarantine.tsv'"
+//! replay quarantine.tsv replay.sql
+//! psql "$DSN" -v ON_ERROR_STOP=1 -f replay.sql
+//!
+//! With ClickHouse, `make replay-clickhouse apply-replay-clickhouse` runs:
+//!
+//! clickhouse client --query "SELECT e.program_id, e.slot, e.tx_signature,
+//! q.tx_index, e.timestamp, e.source, e.log_index, e.instruction_index,
+//! e.inner_instruction_index, e.payload, q.tx_error FROM Quarantine AS q
+//! FINAL JOIN Error AS e FINAL USING (id) FORMAT TabSeparated" >
+//! quarantine.tsv
+//! replay --clickhouse quarantine.tsv replay.sql
+//! clickhouse client --multiquery < replay.sql
+
+use anyhow::{bail, Context, Result};
+use gummy_staking::Dialect;
+
+fn main() -> Result<()> {
+    let mut dialect = Dialect::Postgres;
+    let mut lenient = false;
+    let mut paths = Vec::new();
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--clickhouse" => dialect = Dialect::ClickHouse,
+            "--lenient" => lenient = true,
+            _ => paths.push(arg),
+        }
+    }
+    let [input, output] = paths.as_slice() else {
+        bail!(
+            "usage: replay [--clickhouse] [--lenient] <quarantine.tsv> <output.sql>\n\
+             rows are tab separated: {}",
+            gummy_staking::QUARANTINE_COLUMNS.join(", ")
+        );
+    };
+
+    let rows = std::fs::read_to_string(input).with_context(|| format!("reading {input}"))?;
+    let replay = gummy_staking::replay(&rows, lenient)?;
+    std::fs::write(output, replay.to_sql(dialect)).with_context(|| format!("writing {output}"))?;
+
+    for (key, reason) in replay.failed.iter() {
+        eprintln!("{key}: {reason}");
+    }
+    eprintln!(
+        "recovered {} rows ({} failed attempts), {} still failing",
+        replay.recovered,
+        replay.failed_attempts,
+        replay.failed.len()
+    );
+    if let Some(slot) = replay.resync_from {
+        eprintln!(
+            "warning: {} not updated, resync them from slot {slot}",
+            replay
+                .stale_tables
+                .iter()
+                .copied()
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,446 @@
+use crate::events::{self, GummyEvent};
+use crate::pb::sf::solana::event::v1 as pb;
+use base64::prelude::*;
+use borsh::BorshDeserialize;
+use pb::event::Event;
+use std::fmt::Display;
+
+pub const LOG_EVENT_PREFIX: &str = "Program data: ";
+
+/// Why a payload could not be decoded into an event.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecodeError {
+    Base64Decode,
+    TooShort {
+        len: usize,
+    },
+    UnknownDiscriminator {
+        discriminator: [u8; 8],
+    },
+    BorshDecode {
+        event: &'static str,
+        discriminator: [u8; 8],
+        message: String,
+    },
+    /// The event decoded but did not consume the whole payload, which usually
+    /// means its layout changed.
+    TrailingBytes {
+        event: &'static str,
+        discriminator: [u8; 8],
+        count: usize,
+    },
+}
+
+impl DecodeError {
+    pub fn kind(&self) -> pb::DecodeErrorKind {
+        match self {
+            DecodeError::Base64Decode => pb::DecodeErrorKind::Base64Decode,
+            DecodeError::TooShort { .. } => pb::DecodeErrorKind::TooShort,
+            DecodeError::UnknownDiscriminator { .. } => pb::DecodeErrorKind::UnknownDiscriminator,
+            DecodeError::BorshDecode { .. } => pb::DecodeErrorKind::BorshDecode,
+            DecodeError::TrailingBytes { .. } => pb::DecodeErrorKind::TrailingBytes,
+        }
+    }
+
+    /// The event the discriminator matched, if any.
+    pub fn event(&self) -> Option<&'static str> {
+        match self {
+            DecodeError::BorshDecode { event, .. } | DecodeError::TrailingBytes { event, .. } => {
+                Some(event)
+            }
+            DecodeError::Base64Decode
+            | DecodeError::TooShort { .. }
+            | DecodeError::UnknownDiscriminator { .. } => None,
+        }
+    }
+
+    pub fn discriminator(&self) -> Option<&[u8; 8]> {
+        match self {
+            DecodeError::UnknownDiscriminator { discriminator }
+            | DecodeError::BorshDecode { discriminator, .. }
+            | DecodeError::TrailingBytes { discriminator, .. } => Some(discriminator),
+            DecodeError::Base64Decode | DecodeError::TooShort { .. } => None,
+        }
+    }
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeError::Base64Decode => write!(f, "Error decoding base64"),
+            DecodeError::TooShort { len } => write!(f, "Decoded message too short ({len} bytes)"),
+            DecodeError::UnknownDiscriminator { .. } => {
+                write!(f, "Discriminator does not match known events")
+            }
+            DecodeError::BorshDecode { event, message, .. } => {
+                write!(f, "Error deserializing event '{event}': '{message}'")
+            }
+            DecodeError::TrailingBytes { event, count, .. } => {
+                write!(f, "Event '{event}' is followed by {count} unexpected bytes")
+            }
+        }
+    }
+}
+
+/// How to decode payloads.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DecodeOptions {
+    /// Slot the payload was found at, selecting the layouts in use then.
+    pub slot: u64,
+    /// Accept payloads longer than their layout, as when a program upgrade
+    /// appends fields, instead of failing with `TrailingBytes`.
+    pub lenient: bool,
+}
+
+/// An event together with how it was decoded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Decoded {
+    pub event: GummyEvent,
+    /// Version of the layout the event was read with, see `LAYOUTS`. Events
+    /// without a message of their own, which the output holds as an
+    /// `IdlEvent`, are version 0.
+    pub layout_version: u32,
+    /// Bytes past the end of the layout, only ever non-zero in lenient mode.
+    pub ignored_bytes: usize,
+}
+
+/// A borsh layout of an event. Program upgrades can change an event, so it
+/// may have several layouts, each in use from `since_slot` on.
+struct Layout {
+    discriminator: &'static [u8],
+    event: &'static str,
+    version: u32,
+    since_slot: u64,
+    /// Serialized size of layouts without variable length fields.
+    size: Option<usize>,
+    deserialize: fn(&mut &[u8]) -> borsh::io::Result<GummyEvent>,
+}
+
+/// The layout of the IDL struct of an event, version 1 and in use from slot 0.
+macro_rules! idl_layout {
+    ($event:ident, $discriminator:ident) => {
+        Layout {
+            discriminator: events::$discriminator,
+            event: stringify!($event),
+            version: 1,
+            since_slot: 0,
+            size: events::$event::SIZE,
+            deserialize: |reader| {
+                events::$event::deserialize_reader(reader).map(GummyEvent::$event)
+            },
+        }
+    };
+}
+
+/// Layouts of the events, oldest first for each event. The current ones are
+/// version 1. When an upgrade changes an event, replace its version 1 with a
+/// `Layout` reading the struct it had before, kept in `events.rs` with a
+/// conversion into the new struct and its size as a literal. Then update the
+/// IDL and add the new struct right after it as version 2, with the slot of
+/// the upgrade and its generated `SIZE`. Events of the IDL missing here, such
+/// as those without a message of their own, are read with its layout.
+const LAYOUTS: &[Layout] = &[
+    idl_layout!(Deposit, DISCRIMINATOR_DEPOSIT),
+    idl_layout!(Withdraw, DISCRIMINATOR_WITHDRAW),
+    idl_layout!(SetReferrer, DISCRIMINATOR_SET_REFERRER),
+    idl_layout!(RegisterShortReferrer, DISCRIMINATOR_REGISTER_SHORT_REFERRER),
+    idl_layout!(
+        AdminRegisterShortReferrer,
+        DISCRIMINATOR_ADMIN_REGISTER_SHORT_REFERRER
+    ),
+    idl_layout!(
+        AdminDeleteShortReferrer,
+        DISCRIMINATOR_ADMIN_DELETE_SHORT_REFERRER
+    ),
+    idl_layout!(
+        AdminEmergencyWithdraw,
+        DISCRIMINATOR_ADMIN_EMERGENCY_WITHDRAW
+    ),
+];
+
+/// Decodes the base64 payload of a `Program data:` log line into a typed event.
+pub fn decode_program_data(message: &str, options: &DecodeOptions) -> Result<Decoded, DecodeError> {
+    let Ok(base64_decoded_message) = BASE64_STANDARD.decode(message) else {
+        return Err(DecodeError::Base64Decode);
+    };
+    decode_event(&base64_decoded_message, options)
+}
+
+/// Decodes the data of an Anchor `emit_cpi!` self-invocation, with the
+/// `EVENT_IX_TAG_LE` prefix already stripped.
+pub fn decode_event_instruction_data(
+    data: &[u8],
+    options: &DecodeOptions,
+) -> Result<Decoded, DecodeError> {
+    decode_event(data, options)
+}
+
+/// Decodes a discriminator followed by a borsh serialized event.
+fn decode_event(data: &[u8], options: &DecodeOptions) -> Result<Decoded, DecodeError> {
+    decode_with_layouts(LAYOUTS, data, options)
+}
+
+fn decode_with_layouts(
+    layouts: &'static [Layout],
+    data: &[u8],
+    options: &DecodeOptions,
+) -> Result<Decoded, DecodeError> {
+    let Some((discriminator, mut reader)) = data.split_first_chunk::<8>() else {
+        return Err(DecodeError::TooShort { len: data.len() });
+    };
+    let (deserialize, name, layout_version) =
+        match select_layout(layouts, discriminator, reader.len(), options.slot) {
+            Some(layout) => (layout.deserialize, layout.event, layout.version),
+            None => {
+                let Some(idl_event) = events::EVENTS
+                    .iter()
+                    .find(|event| event.discriminator == discriminator)
+                else {
+                    return Err(DecodeError::UnknownDiscriminator {
+                        discriminator: *discriminator,
+                    });
+                };
+                (idl_event.deserialize, idl_event.name, 0)
+            }
+        };
+
+    let event = deserialize(&mut reader).map_err(|e| DecodeError::BorshDecode {
+        event: name,
+        discriminator: *discriminator,
+        message: e.to_string(),
+    })?;
+    if !reader.is_empty() && !options.lenient {
+        return Err(DecodeError::TrailingBytes {
+            event: name,
+            discriminator: *discriminator,
+            count: reader.len(),
+        });
+    }
+    Ok(Decoded {
+        event,
+        layout_version,
+        ignored_bytes: reader.len(),
+    })
+}
+
+/// Picks the layout of an event: among those in use at `slot`, the latest
+/// one whose size matches the payload, or else the latest one. Payloads from
+/// before the first known layout use that one.
+fn select_layout(
+    layouts: &'static [Layout],
+    discriminator: &[u8],
+    len: usize,
+    slot: u64,
+) -> Option<&'static Layout> {
+    let layouts = || {
+        layouts
+            .iter()
+            .filter(move |layout| layout.discriminator == discriminator)
+    };
+    let in_use = || layouts().filter(|layout| layout.since_slot <= slot);
+    in_use()
+        .rfind(|layout| layout.size == Some(len))
+        .or_else(|| in_use().next_back())
+        .or_else(|| layouts().next())
+}
+
+impl Event {
+    pub fn name(&self) -> &str {
+        match self {
+            Event::Deposit(_) => "Deposit",
+            Event::Withdraw(_) => "Withdraw",
+            Event::SetReferrer(_) => "SetReferrer",
+            Event::RegisterShortReferrer(_) => "RegisterShortReferrer",
+            Event::AdminRegisterShortReferrer(_) => "AdminRegisterShortReferrer",
+            Event::AdminDeleteShortReferrer(_) => "AdminDeleteShortReferrer",
+            Event::AdminEmergencyWithdraw(_) => "AdminEmergencyWithdraw",
+            Event::IdlEvent(event) => &event.name,
+        }
+    }
+
+    /// The staker the event is about, if any.
+    pub fn user(&self) -> Option<&str> {
+        match self {
+            Event::Deposit(event) => Some(&event.user),
+            Event::Withdraw(event) => Some(&event.user),
+            Event::SetReferrer(event) => Some(&event.user),
+            Event::AdminEmergencyWithdraw(event) => Some(&event.user),
+            Event::RegisterShortReferrer(_)
+            | Event::AdminRegisterShortReferrer(_)
+            | Event::AdminDeleteShortReferrer(_)
+            | Event::IdlEvent(_) => None,
+        }
+    }
+
+    /// The staker and their staked total after the event, for events that
+    /// move tokens in or out of a position.
+    pub fn staked_total(&self) -> Option<(&str, u64)> {
+        match self {
+            Event::Deposit(event) => Some((&event.user, event.total_amount)),
+            Event::Withdraw(event) => Some((&event.user, event.total_amount)),
+            Event::AdminEmergencyWithdraw(event) => Some((&event.user, event.total_amount)),
+            Event::SetReferrer(_)
+            | Event::RegisterShortReferrer(_)
+            | Event::AdminRegisterShortReferrer(_)
+            | Event::AdminDeleteShortReferrer(_)
+            | Event::IdlEvent(_) => None,
+        }
+    }
+
+    /// The user a withdrawal pays out to.
+    pub fn withdrawing_user(&self) -> Option<&str> {
+        match self {
+            Event::Withdraw(event) => Some(&event.user),
+            Event::AdminEmergencyWithdraw(event) => Some(&event.user),
+            Event::Deposit(_)
+            | Event::SetReferrer(_)
+            | Event::RegisterShortReferrer(_)
+            | Event::AdminRegisterShortReferrer(_)
+            | Event::AdminDeleteShortReferrer(_)
+            | Event::IdlEvent(_) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::params::Params;
+    use borsh::BorshSerialize;
+    use events::Pubkey;
+
+    const UPGRADE_SLOT: u64 = 1000;
+
+    /// Deposit as if an upgrade at `UPGRADE_SLOT` appended a fee to it.
+    const UPGRADED_LAYOUTS: &[Layout] = &[
+        Layout {
+            discriminator: events::DISCRIMINATOR_DEPOSIT,
+            event: "Deposit",
+            version: 1,
+            since_slot: 0,
+            size: Some(84),
+            deserialize: |reader| {
+                events::Deposit::deserialize_reader(reader).map(GummyEvent::Deposit)
+            },
+        },
+        Layout {
+            discriminator: events::DISCRIMINATOR_DEPOSIT,
+            event: "Deposit",
+            version: 2,
+            since_slot: UPGRADE_SLOT,
+            size: Some(92),
+            deserialize: |reader| {
+                let event = events::Deposit::deserialize_reader(reader)?;
+                let _fee = u64::deserialize_reader(reader)?;
+                Ok(GummyEvent::Deposit(event))
+            },
+        },
+    ];
+
+    fn deposit() -> events::Deposit {
+        events::Deposit {
+            user: Pubkey([1; 32]),
+            amount: 2,
+            total_amount: 3,
+            lock_expires: 4,
+            referrer: Pubkey([5; 32]),
+        }
+    }
+
+    /// A deposit followed by `extra` bytes.
+    fn payload(extra: usize) -> Vec<u8> {
+        let mut data = events::DISCRIMINATOR_DEPOSIT.to_vec();
+        deposit().serialize(&mut data).unwrap();
+        data.extend(vec![7; extra]);
+        data
+    }
+
+    fn decode(data: &[u8], slot: u64, lenient: bool) -> Result<Decoded, DecodeError> {
+        decode_with_layouts(UPGRADED_LAYOUTS, data, &DecodeOptions { slot, lenient })
+    }
+
+    fn version(len: usize, slot: u64) -> Option<u32> {
+        select_layout(UPGRADED_LAYOUTS, events::DISCRIMINATOR_DEPOSIT, len, slot)
+            .map(|layout| layout.version)
+    }
+
+    #[test]
+    fn selects_the_layouts_in_use_at_the_slot() {
+        assert_eq!(version(84, 0), Some(1));
+        assert_eq!(version(92, UPGRADE_SLOT - 1), Some(1));
+        assert_eq!(version(92, UPGRADE_SLOT), Some(2));
+        assert_eq!(version(92, u64::MAX), Some(2));
+        // Payloads from before the upgrade can still be found after it.
+        assert_eq!(version(84, UPGRADE_SLOT), Some(1));
+        assert_eq!(version(100, UPGRADE_SLOT), Some(2));
+        assert_eq!(
+            select_layout(UPGRADED_LAYOUTS, events::DISCRIMINATOR_WITHDRAW, 40, 0)
+                .map(|layout| layout.event),
+            None
+        );
+    }
+
+    #[test]
+    fn decodes_with_the_layout_of_the_slot() {
+        let expected = GummyEvent::Deposit(deposit());
+        let decoded = decode(&payload(8), UPGRADE_SLOT, false).unwrap();
+        assert_eq!(decoded.event, expected);
+        assert_eq!(decoded.layout_version, 2);
+        assert_eq!(decoded.ignored_bytes, 0);
+
+        let decoded = decode(&payload(0), UPGRADE_SLOT, false).unwrap();
+        assert_eq!(decoded.layout_version, 1);
+
+        // Before the upgrade, the fee can only be trailing bytes.
+        assert_eq!(
+            decode(&payload(8), UPGRADE_SLOT - 1, false),
+            Err(DecodeError::TrailingBytes {
+                event: "Deposit",
+                discriminator: *events::DISCRIMINATOR_DEPOSIT.first_chunk().unwrap(),
+                count: 8,
+            })
+        );
+    }
+
+    #[test]
+    fn indexes_events_appended_a_field_by_default() {
+        let options = |lenient| DecodeOptions {
+            slot: u64::MAX,
+            lenient,
+        };
+        let default = Params::parse("").unwrap().lenient_decoding;
+        let decoded = decode_event(&payload(8), &options(default)).unwrap();
+        assert_eq!(decoded.event, GummyEvent::Deposit(deposit()));
+        assert_eq!(decoded.layout_version, 1);
+        assert_eq!(decoded.ignored_bytes, 8);
+
+        let decoded = decode_event(&payload(0), &options(false)).unwrap();
+        assert_eq!(decoded.layout_version, 1);
+        assert!(matches!(
+            decode_event(&payload(8), &options(false)),
+            Err(DecodeError::TrailingBytes { count: 8, .. })
+        ));
+    }
+
+    #[test]
+    fn lenient_mode_ignores_trailing_bytes() {
+        let decoded = decode(&payload(8), UPGRADE_SLOT - 1, true).unwrap();
+        assert_eq!(decoded.event, GummyEvent::Deposit(deposit()));
+        assert_eq!(decoded.layout_version, 1);
+        assert_eq!(decoded.ignored_bytes, 8);
+
+        let decoded = decode(&payload(11), UPGRADE_SLOT, true).unwrap();
+        assert_eq!(decoded.layout_version, 2);
+        assert_eq!(decoded.ignored_bytes, 3);
+
+        assert!(matches!(
+            decode(&payload(11), UPGRADE_SLOT, false),
+            Err(DecodeError::TrailingBytes { count: 3, .. })
+        ));
+        // Missing bytes are never tolerated.
+        assert!(matches!(
+            decode(&payload(0)[..80], 0, true),
+            Err(DecodeError::BorshDecode { .. })
+        ));
+    }
+}
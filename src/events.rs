@@ -1,70 +1,254 @@
-use borsh::BorshDeserialize;
+//! Events emitted by the staking program, as described by its Anchor IDL in
+//! `idl/gummy_staking.json`. To index a new event, add it to the IDL: it is
+//! then decoded into an `IdlEvent` and written to a table named after it,
+//! whose DDL `cargo test` checks is in the schemas, see `src/schema.rs`.
+//!
+//! Giving the event a message and tables of its own takes these steps:
+//!
+//! 1. Add the message to `proto/event.proto` and to the `Event.event` oneof,
+//!    with the fields of the IDL. The conversion from the event struct is
+//!    generated, and fails to compile if the fields do not match.
+//! 2. Complete the matches on `pb::event::Event` the compiler points at: the
+//!    accessors of `src/decoder.rs` and `build_tables` in `src/lib.rs`.
+//! 3. Replace the generated table in `schema.sql` and `schema.clickhouse.sql`.
+//!
+//! When a program upgrade changes an event, see `LAYOUTS` in `src/decoder.rs`.
 
-#[derive(BorshDeserialize, Debug)]
-pub struct Pubkey([u8; 32]);
+use crate::tables::Row;
+use borsh::{BorshDeserialize, BorshSerialize};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use sha2_const_stable::Sha256;
+use std::fmt;
+use std::str::FromStr;
+use substreams::Hex;
+
+#[derive(
+    BorshDeserialize, BorshSerialize, Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord,
+)]
+pub struct Pubkey(pub [u8; 32]);
 impl AsRef<[u8]> for Pubkey {
     fn as_ref(&self) -> &[u8] {
         &self.0[..]
     }
 }
-impl Pubkey {
-    pub fn to_string(&self) -> String {
-        bs58::encode(self.0).into_string()
+impl fmt::Display for Pubkey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&bs58::encode(self.0).into_string())
     }
 }
 
-pub const DISCRIMINATOR_DEPOSIT: &[u8] = b"\x3e\xcd\xf2\xaf\xf4\xa9\x88\x34";
-// echo -n 'event:Deposit' | shasum  -a256 | cut -c -16 | sed -E 's/(..)/\\x\1/g'
-#[derive(BorshDeserialize, Debug)]
-pub struct Deposit {
-    pub user: Pubkey,
-    pub amount: u64,
-    pub total_amount: u64,
-    pub lock_expires: u32,
-    pub referrer: Pubkey,
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParsePubkeyError;
+impl fmt::Display for ParsePubkeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Invalid base58 public key")
+    }
 }
+impl std::error::Error for ParsePubkeyError {}
 
-pub const DISCRIMINATOR_WITHDRAW: &[u8] = b"\xc0\xf1\xc9\xd9\x46\x96\x5a\xf7";
-#[derive(BorshDeserialize, Debug)]
-pub struct Withdraw {
-    pub user: Pubkey,
-    pub total_amount: u64,
+impl FromStr for Pubkey {
+    type Err = ParsePubkeyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut bytes = [0; 32];
+        match bs58::decode(s).onto(&mut bytes) {
+            Ok(32) => Ok(Pubkey(bytes)),
+            _ => Err(ParsePubkeyError),
+        }
+    }
 }
 
-pub const DISCRIMINATOR_SET_REFERRER: &[u8] = b"\xbe\xfb\x76\x7f\x49\x7b\x52\xb8";
-#[derive(BorshDeserialize, Debug)]
-pub struct SetReferrer {
-    pub user: Pubkey,
-    pub old_referrer: Pubkey,
-    pub new_referrer: Pubkey,
+/// Serialized as base58, like `Display`.
+impl Serialize for Pubkey {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+impl<'de> Deserialize<'de> for Pubkey {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = <String as Deserialize>::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
 }
 
-pub const DISCRIMINATOR_REGISTER_SHORT_REFERRER: &[u8] = b"\x28\x71\xfb\x60\x2c\x5b\xf4\xc1";
-#[derive(BorshDeserialize, Debug)]
-pub struct RegisterShortReferrer {
-    pub full: Pubkey,
-    pub short: Vec<u8>,
+/// The first 8 bytes of `sha256("<namespace>:<name>")`, as Anchor derives
+/// discriminators.
+pub const fn discriminator(namespace: &str, name: &str) -> [u8; 8] {
+    let hash = Sha256::new()
+        .update(namespace.as_bytes())
+        .update(b":")
+        .update(name.as_bytes())
+        .finalize();
+    let mut discriminator = [0; 8];
+    let mut i = 0;
+    while i < discriminator.len() {
+        discriminator[i] = hash[i];
+        i += 1;
+    }
+    discriminator
 }
 
-pub const DISCRIMINATOR_ADMIN_REGISTER_SHORT_REFERRER: &[u8] = b"\x69\x8b\x57\xae\x7d\xc8\x06\x6f";
-#[derive(BorshDeserialize, Debug)]
-pub struct AdminRegisterShortReferrer {
-    pub full: Pubkey,
-    pub short: Vec<u8>,
-    pub initiator: Pubkey,
+/// Prefix of the instruction data of Anchor `emit_cpi!` self-invocations,
+/// followed by the event discriminator and the serialized event. It is the
+/// hash of `anchor:event`, byte swapped as Anchor reads it as a big endian
+/// integer.
+pub const EVENT_IX_TAG_LE: &[u8] = b"\xe4\x45\xa5\x2e\x51\xcb\x9a\x1d";
+
+/// An event of the IDL.
+pub struct EventInfo {
+    pub name: &'static str,
+    pub discriminator: &'static [u8],
+    /// Reads the event with the current layout of the IDL.
+    pub deserialize: fn(&mut &[u8]) -> borsh::io::Result<GummyEvent>,
+    /// Whether the event has a message of its own in the output, rather than
+    /// being written as an `IdlEvent`.
+    pub message: bool,
+    /// The columns `set_columns` writes, one per field.
+    pub columns: &'static [Column],
+}
+
+pub struct Column {
+    pub name: &'static str,
+    pub column_type: ColumnType,
+    /// Set for optional fields, whose column is left unset for `None`.
+    pub nullable: bool,
 }
 
-pub const DISCRIMINATOR_ADMIN_DELETE_SHORT_REFERRER: &[u8] = b"\x1c\x12\x1a\x34\x94\xca\xff\x40";
-#[derive(BorshDeserialize, Debug)]
-pub struct AdminDeleteShortReferrer {
-    pub short: Vec<u8>,
-    pub initiator: Pubkey,
+/// What a field is written as. Sequences and addresses are text, see
+/// `set_columns`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColumnType {
+    Bool,
+    Integer { signed: bool, bits: u32 },
+    Text,
 }
 
-pub const DISCRIMINATOR_ADMIN_EMERGENCY_WITHDRAW: &[u8] = b"\x65\xa3\xa1\x8b\xa3\x9d\x9d\xe3";
-#[derive(BorshDeserialize, Debug)]
-pub struct AdminEmergencyWithdraw {
-    pub user: Pubkey,
-    pub total_amount: u64,
-    pub initiator: Pubkey,
-}
\ No newline at end of file
+// The event structs, their `DISCRIMINATOR_*` constants, `SIZE`s and
+// `set_columns` mappings, their conversions into proto messages, the `EVENTS`
+// table and the `GummyEvent` enum are generated by `build.rs` from the Anchor
+// IDL in `idl/`.
+include!(concat!(env!("OUT_DIR"), "/events.rs"));
+
+/// Deserializes the event `name` from `data` and sets a column per field on
+/// `row`.
+pub(crate) fn set_event_columns<'a, R: Row>(
+    name: &str,
+    mut data: &[u8],
+    row: &'a mut R,
+) -> borsh::io::Result<&'a mut R> {
+    let Some(event) = EVENTS.iter().find(|event| event.name == name) else {
+        return Err(borsh::io::Error::new(
+            borsh::io::ErrorKind::InvalidInput,
+            format!("unknown event {name}"),
+        ));
+    };
+    Ok((event.deserialize)(&mut data)?.set_columns(row))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sha2::{Digest, Sha256};
+
+    fn expected(preimage: &str) -> Vec<u8> {
+        Sha256::digest(preimage)[..8].to_vec()
+    }
+
+    #[test]
+    fn event_ix_tag_matches_anchor() {
+        let mut tag = expected("anchor:event");
+        tag.reverse();
+        assert_eq!(EVENT_IX_TAG_LE, tag);
+    }
+
+    #[test]
+    fn discriminators_match_event_names() {
+        for event in EVENTS {
+            assert_eq!(
+                event.discriminator,
+                expected(&format!("event:{}", event.name)),
+                "{}",
+                event.name
+            );
+        }
+    }
+
+    #[test]
+    fn discriminators_match_idl() {
+        let idl: serde_json::Value =
+            serde_json::from_str(include_str!("../idl/gummy_staking.json")).unwrap();
+        let idl_events = idl["events"].as_array().unwrap();
+        assert_eq!(idl_events.len(), EVENTS.len());
+        for (idl_event, event) in idl_events.iter().zip(EVENTS) {
+            assert_eq!(idl_event["name"], event.name);
+            let discriminator: Vec<u8> =
+                serde_json::from_value(idl_event["discriminator"].clone()).unwrap();
+            assert_eq!(event.discriminator, discriminator, "{}", event.name);
+        }
+    }
+
+    #[test]
+    fn pubkey_round_trips_as_base58() {
+        let address = crate::fixtures::PROGRAM_ID;
+        let pubkey: Pubkey = address.parse().unwrap();
+        assert_eq!(pubkey.to_string(), address);
+        assert_eq!(
+            serde_json::to_string(&pubkey).unwrap(),
+            format!("\"{address}\"")
+        );
+        assert_eq!(
+            serde_json::from_str::<Pubkey>(&format!("\"{address}\"")).unwrap(),
+            pubkey
+        );
+        assert_eq!("".parse::<Pubkey>(), Err(ParsePubkeyError));
+        assert_eq!(address[..20].parse::<Pubkey>(), Err(ParsePubkeyError));
+        assert_eq!(
+            format!("{address}1").parse::<Pubkey>(),
+            Err(ParsePubkeyError)
+        );
+        assert_eq!("0OIl".parse::<Pubkey>(), Err(ParsePubkeyError));
+    }
+
+    #[test]
+    fn events_serialize_to_json() {
+        let event = GummyEvent::Withdraw(Withdraw {
+            user: Pubkey([1; 32]),
+            total_amount: 5,
+        });
+        let json = serde_json::to_string(&event).unwrap();
+        assert_eq!(
+            json,
+            format!(
+                r#"{{"Withdraw":{{"user":"{}","total_amount":5}}}}"#,
+                Pubkey([1; 32])
+            )
+        );
+        assert_eq!(serde_json::from_str::<GummyEvent>(&json).unwrap(), event);
+    }
+
+    #[test]
+    fn discriminator_constants() {
+        assert_eq!(DISCRIMINATOR_DEPOSIT, b"\x3e\xcd\xf2\xaf\xf4\xa9\x88\x34");
+        assert_eq!(DISCRIMINATOR_WITHDRAW, b"\xc0\xf1\xc9\xd9\x46\x96\x5a\xf7");
+        assert_eq!(
+            DISCRIMINATOR_SET_REFERRER,
+            b"\xbe\xfb\x76\x7f\x49\x7b\x52\xb8"
+        );
+        assert_eq!(
+            DISCRIMINATOR_REGISTER_SHORT_REFERRER,
+            b"\x28\x71\xfb\x60\x2c\x5b\xf4\xc1"
+        );
+        assert_eq!(
+            DISCRIMINATOR_ADMIN_REGISTER_SHORT_REFERRER,
+            b"\x69\x8b\x57\xae\x7d\xc8\x06\x6f"
+        );
+        assert_eq!(
+            DISCRIMINATOR_ADMIN_DELETE_SHORT_REFERRER,
+            b"\x1c\x12\x1a\x34\x94\xca\xff\x40"
+        );
+        assert_eq!(
+            DISCRIMINATOR_ADMIN_EMERGENCY_WITHDRAW,
+            b"\x65\xa3\xa1\x8b\xa3\x9d\x9d\xe3"
+        );
+    }
+}
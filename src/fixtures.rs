@@ -0,0 +1,287 @@
+//! Synthetic blocks and transactions carrying chosen events, to run the
+//! modules natively in tests.
+//!
+//! ```
+//! use gummy_staking::events::{GummyEvent, Pubkey, Withdraw};
+//! use gummy_staking::fixtures::{self, TransactionBuilder};
+//!
+//! let program_id: Pubkey = fixtures::PROGRAM_ID.parse().unwrap();
+//! let withdraw = GummyEvent::Withdraw(Withdraw {
+//!     user: Pubkey([2; 32]),
+//!     total_amount: 0,
+//! });
+//! let transaction = TransactionBuilder::new([1; 64])
+//!     .instruction(&program_id, &[fixtures::program_data_line(&withdraw)])
+//!     .build();
+//! let block = fixtures::block(264062815, 1_717_000_000, vec![transaction]);
+//! let events = gummy_staking::gummy_events("", &block).unwrap();
+//! assert_eq!(events.events.len(), 1);
+//! ```
+
+use crate::decoder::LOG_EVENT_PREFIX;
+use crate::events::{GummyEvent, Pubkey, EVENT_IX_TAG_LE};
+use crate::params::DEFAULT_PROGRAM_ID;
+use crate::transaction_error::{instruction_error_tag, transaction_error_tag};
+use base64::prelude::*;
+use substreams_solana::pb::sf::solana::r#type::v1::{
+    Block, CompiledInstruction, ConfirmedTransaction, InnerInstruction, InnerInstructions, Message,
+    MessageHeader, TokenBalance, Transaction, TransactionError, TransactionStatusMeta,
+    UiTokenAmount, UnixTimestamp,
+};
+
+/// The program the fixtures invoke, the default of the `program_ids` param.
+pub const PROGRAM_ID: &str = DEFAULT_PROGRAM_ID;
+
+/// Signs and pays for the transactions built by `TransactionBuilder`.
+pub const FEE_PAYER: Pubkey = Pubkey([1; 32]);
+
+/// The `Program data:` log line Anchor's `emit!` writes for `event`.
+pub fn program_data_line(event: &GummyEvent) -> String {
+    program_data_line_from_bytes(&borsh::to_vec(event).expect("writing to a Vec"))
+}
+
+/// A `Program data:` log line with an arbitrary payload, such as a malformed
+/// event.
+pub fn program_data_line_from_bytes(data: &[u8]) -> String {
+    format!("{LOG_EVENT_PREFIX}{}", BASE64_STANDARD.encode(data))
+}
+
+/// The bincode encoding of the runtime's `TransactionError` variant `name`
+/// followed by its `fields`, already encoded, as `TransactionStatusMeta::err`
+/// holds it. `None` for an unknown variant.
+pub fn transaction_error(name: &str, fields: &[u8]) -> Option<Vec<u8>> {
+    let mut err = transaction_error_tag(name)?.to_le_bytes().to_vec();
+    err.extend(fields);
+    Some(err)
+}
+
+/// The encoded fields of `InstructionError(index, error)`, `error` being the
+/// `InstructionError` variant `name` followed by its encoded `fields`.
+pub fn instruction_error(index: u8, name: &str, fields: &[u8]) -> Option<Vec<u8>> {
+    let mut encoded = vec![index];
+    encoded.extend(instruction_error_tag(name)?.to_le_bytes());
+    encoded.extend(fields);
+    Some(encoded)
+}
+
+/// The instruction data of the self-invocation Anchor's `emit_cpi!` makes for
+/// `event`.
+pub fn event_instruction_data(event: &GummyEvent) -> Vec<u8> {
+    let mut data = EVENT_IX_TAG_LE.to_vec();
+    data.extend(borsh::to_vec(event).expect("writing to a Vec"));
+    data
+}
+
+/// A block at `slot` holding `transactions`, in order.
+pub fn block(slot: u64, timestamp: i64, transactions: Vec<ConfirmedTransaction>) -> Block {
+    Block {
+        slot,
+        parent_slot: slot.saturating_sub(1),
+        block_time: Some(UnixTimestamp { timestamp }),
+        transactions,
+        ..Default::default()
+    }
+}
+
+/// Builds a transaction out of top-level instructions, with the logs and
+/// inner instructions the runtime would record for them.
+pub struct TransactionBuilder {
+    signature: Vec<u8>,
+    account_keys: Vec<Vec<u8>>,
+    instructions: Vec<CompiledInstruction>,
+    inner_instructions: Vec<InnerInstructions>,
+    log_messages: Vec<String>,
+    /// Index of the log line closing the last top-level instruction, and the
+    /// program it closes.
+    last_closing_line: Option<(usize, Pubkey)>,
+    pre_token_balances: Vec<TokenBalance>,
+    post_token_balances: Vec<TokenBalance>,
+    err: Option<Vec<u8>>,
+}
+
+impl TransactionBuilder {
+    pub fn new(signature: impl Into<Vec<u8>>) -> Self {
+        TransactionBuilder {
+            signature: signature.into(),
+            account_keys: vec![FEE_PAYER.0.to_vec()],
+            instructions: Vec::new(),
+            inner_instructions: Vec::new(),
+            log_messages: Vec::new(),
+            last_closing_line: None,
+            pre_token_balances: Vec::new(),
+            post_token_balances: Vec::new(),
+            err: None,
+        }
+    }
+
+    /// Adds a top-level instruction of `program_id` that logs `lines`.
+    pub fn instruction(mut self, program_id: &Pubkey, lines: &[String]) -> Self {
+        self.push_instruction(program_id);
+        self.log_messages
+            .push(format!("Program {program_id} invoke [1]"));
+        self.log_messages.extend(lines.iter().cloned());
+        self.close(program_id);
+        self
+    }
+
+    /// Adds a top-level instruction of `caller` that invokes `program_id`,
+    /// which logs `lines`.
+    pub fn cpi(mut self, caller: &Pubkey, program_id: &Pubkey, lines: &[String]) -> Self {
+        self.push_instruction(caller);
+        self.push_inner_instruction(program_id, Vec::new());
+        self.log_messages
+            .push(format!("Program {caller} invoke [1]"));
+        self.log_messages
+            .push(format!("Program {program_id} invoke [2]"));
+        self.log_messages.extend(lines.iter().cloned());
+        self.log_messages
+            .push(format!("Program {program_id} success"));
+        self.close(caller);
+        self
+    }
+
+    /// Adds a top-level instruction of `program_id` that emits `events` with
+    /// `emit_cpi!`, each through a self-invocation.
+    pub fn emit_cpi(mut self, program_id: &Pubkey, events: &[GummyEvent]) -> Self {
+        self.push_instruction(program_id);
+        self.log_messages
+            .push(format!("Program {program_id} invoke [1]"));
+        for event in events {
+            self.push_inner_instruction(program_id, event_instruction_data(event));
+            self.log_messages
+                .push(format!("Program {program_id} invoke [2]"));
+            self.log_messages
+                .push(format!("Program {program_id} success"));
+        }
+        self.close(program_id);
+        self
+    }
+
+    /// Makes the last top-level instruction fail with the custom program
+    /// error `code`, as Anchor errors do.
+    pub fn failed(mut self, code: u32) -> Self {
+        let index = self.instructions.len().saturating_sub(1);
+        if let Some((line, program_id)) = self.last_closing_line {
+            self.log_messages[line] =
+                format!("Program {program_id} failed: custom program error: {code:#x}");
+        }
+        let error = instruction_error(index as u8, "Custom", &code.to_le_bytes());
+        self.err = transaction_error("InstructionError", &error.expect("a known variant"));
+        self
+    }
+
+    /// Records that the token account `account` of `owner` held `pre`, then
+    /// `post` tokens of `mint`.
+    pub fn token_balance(
+        mut self,
+        account: &Pubkey,
+        owner: &Pubkey,
+        mint: &Pubkey,
+        pre: u64,
+        post: u64,
+    ) -> Self {
+        let account_index = self.account_index(account);
+        let balance = |amount: u64| TokenBalance {
+            account_index,
+            mint: mint.to_string(),
+            ui_token_amount: Some(UiTokenAmount {
+                amount: amount.to_string(),
+                ..Default::default()
+            }),
+            owner: owner.to_string(),
+            program_id: String::new(),
+        };
+        self.pre_token_balances.push(balance(pre));
+        self.post_token_balances.push(balance(post));
+        self
+    }
+
+    /// Sets the data of the last top-level instruction, empty by default.
+    pub fn data(mut self, data: &[u8]) -> Self {
+        if let Some(instruction) = self.instructions.last_mut() {
+            instruction.data = data.to_vec();
+        }
+        self
+    }
+
+    /// Keeps the first `lines` log lines, followed by the line the runtime
+    /// writes when a transaction logs more than its limit.
+    pub fn truncate_logs(mut self, lines: usize) -> Self {
+        self.log_messages.truncate(lines);
+        self.log_messages.push("Log truncated".to_string());
+        self
+    }
+
+    pub fn build(self) -> ConfirmedTransaction {
+        ConfirmedTransaction {
+            transaction: Some(Transaction {
+                signatures: vec![self.signature],
+                message: Some(Message {
+                    header: Some(MessageHeader {
+                        num_required_signatures: 1,
+                        num_readonly_signed_accounts: 0,
+                        num_readonly_unsigned_accounts: self.account_keys.len() as u32 - 1,
+                    }),
+                    account_keys: self.account_keys,
+                    instructions: self.instructions,
+                    ..Default::default()
+                }),
+            }),
+            meta: Some(TransactionStatusMeta {
+                err: self.err.map(|err| TransactionError { err }),
+                inner_instructions: self.inner_instructions,
+                log_messages: self.log_messages,
+                pre_token_balances: self.pre_token_balances,
+                post_token_balances: self.post_token_balances,
+                ..Default::default()
+            }),
+        }
+    }
+
+    fn account_index(&mut self, key: &Pubkey) -> u32 {
+        let position = match self.account_keys.iter().position(|k| k == key.as_ref()) {
+            Some(position) => position,
+            None => {
+                self.account_keys.push(key.0.to_vec());
+                self.account_keys.len() - 1
+            }
+        };
+        position as u32
+    }
+
+    fn push_instruction(&mut self, program_id: &Pubkey) {
+        let program_id_index = self.account_index(program_id);
+        self.instructions.push(CompiledInstruction {
+            program_id_index,
+            accounts: vec![0],
+            data: Vec::new(),
+        });
+    }
+
+    fn push_inner_instruction(&mut self, program_id: &Pubkey, data: Vec<u8>) {
+        let program_id_index = self.account_index(program_id);
+        let index = self.instructions.len() as u32 - 1;
+        if self.inner_instructions.last().map(|inner| inner.index) != Some(index) {
+            self.inner_instructions.push(InnerInstructions {
+                index,
+                instructions: Vec::new(),
+            });
+        }
+        let inner = self.inner_instructions.last_mut().expect("pushed above");
+        inner.instructions.push(InnerInstruction {
+            program_id_index,
+            accounts: vec![0],
+            data,
+            stack_height: Some(2),
+        });
+    }
+
+    fn close(&mut self, program_id: &Pubkey) {
+        self.log_messages.push(format!(
+            "Program {program_id} consumed 10000 of 200000 compute units"
+        ));
+        self.log_messages
+            .push(format!("Program {program_id} success"));
+        self.last_closing_line = Some((self.log_messages.len() - 1, *program_id));
+    }
+}
@@ -0,0 +1,280 @@
+//! Access to the instructions of a transaction, used to pick up events emitted
+//! with Anchor's `emit_cpi!` and to spot invocations missing from the logs.
+
+use crate::events::EVENT_IX_TAG_LE;
+use substreams_solana::pb::sf::solana::r#type::v1::{Message, TransactionStatusMeta};
+
+/// An instruction invoking the program, top-level or through a CPI.
+pub struct ProgramInstruction<'a> {
+    pub instruction_index: u32,
+    /// `None` for top-level instructions.
+    pub inner_instruction_index: Option<u32>,
+    pub data: &'a [u8],
+}
+
+impl<'a> ProgramInstruction<'a> {
+    /// The event data if this is an `emit_cpi!` self-invocation, without the
+    /// tag.
+    pub fn event_data(&self) -> Option<&'a [u8]> {
+        self.inner_instruction_index?;
+        self.data.strip_prefix(EVENT_IX_TAG_LE)
+    }
+}
+
+/// The data of an `emit_cpi!` self-invocation, without the tag.
+pub struct EventInstruction<'a> {
+    pub instruction_index: u32,
+    pub inner_instruction_index: u32,
+    pub data: &'a [u8],
+}
+
+/// All the accounts a transaction can reference by index: the static keys of
+/// the message, then the addresses loaded from lookup tables.
+pub fn account_keys<'a>(message: &'a Message, meta: &'a TransactionStatusMeta) -> Vec<&'a [u8]> {
+    message
+        .account_keys
+        .iter()
+        .chain(meta.loaded_writable_addresses.iter())
+        .chain(meta.loaded_readonly_addresses.iter())
+        .map(|key| key.as_slice())
+        .collect()
+}
+
+/// Programs invoked by a transaction, either by a top-level instruction or
+/// through a CPI.
+pub fn invoked_program_ids<'a>(
+    account_keys: &[&'a [u8]],
+    message: &'a Message,
+    meta: &'a TransactionStatusMeta,
+) -> Vec<&'a [u8]> {
+    let program_id_indexes = message
+        .instructions
+        .iter()
+        .map(|instruction| instruction.program_id_index)
+        .chain(
+            meta.inner_instructions
+                .iter()
+                .flat_map(|inner_instructions| {
+                    inner_instructions
+                        .instructions
+                        .iter()
+                        .map(|instruction| instruction.program_id_index)
+                }),
+        );
+    program_id_indexes
+        .filter_map(|program_id_index| account_keys.get(program_id_index as usize).copied())
+        .collect()
+}
+
+/// Instructions invoking `program_id`, in execution order: each top-level
+/// instruction is followed by its inner instructions.
+pub fn program_instructions<'a>(
+    program_id: &'a [u8],
+    account_keys: &'a [&'a [u8]],
+    message: &'a Message,
+    meta: &'a TransactionStatusMeta,
+) -> Vec<ProgramInstruction<'a>> {
+    let invokes_program =
+        |program_id_index: u32| account_keys.get(program_id_index as usize) == Some(&program_id);
+
+    let mut instructions = Vec::new();
+    for (instruction_index, instruction) in message.instructions.iter().enumerate() {
+        let instruction_index = instruction_index as u32;
+        if invokes_program(instruction.program_id_index) {
+            instructions.push(ProgramInstruction {
+                instruction_index,
+                inner_instruction_index: None,
+                data: &instruction.data,
+            });
+        }
+        let inner_instructions = meta
+            .inner_instructions
+            .iter()
+            .filter(|inner_instructions| inner_instructions.index == instruction_index)
+            .flat_map(|inner_instructions| inner_instructions.instructions.iter().enumerate());
+        for (inner_instruction_index, instruction) in inner_instructions {
+            if invokes_program(instruction.program_id_index) {
+                instructions.push(ProgramInstruction {
+                    instruction_index,
+                    inner_instruction_index: Some(inner_instruction_index as u32),
+                    data: &instruction.data,
+                });
+            }
+        }
+    }
+    instructions
+}
+
+/// The `emit_cpi!` self-invocations among `instructions`.
+pub fn event_instructions<'a>(
+    instructions: &'a [ProgramInstruction<'a>],
+) -> impl Iterator<Item = EventInstruction<'a>> {
+    instructions.iter().filter_map(|instruction| {
+        Some(EventInstruction {
+            instruction_index: instruction.instruction_index,
+            inner_instruction_index: instruction.inner_instruction_index?,
+            data: instruction.event_data()?,
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use substreams_solana::pb::sf::solana::r#type::v1::{
+        CompiledInstruction, InnerInstruction, InnerInstructions,
+    };
+
+    const PROGRAM: &[u8] = &[1; 32];
+    const STATIC: &[u8] = &[2; 32];
+    const WRITABLE: &[u8] = &[3; 32];
+    const READONLY: &[u8] = &[4; 32];
+
+    /// Program id index and data of an instruction.
+    type Instruction = (u32, Vec<u8>);
+
+    fn tagged(data: &[u8]) -> Vec<u8> {
+        [EVENT_IX_TAG_LE, data].concat()
+    }
+
+    fn transaction(
+        account_keys: &[&[u8]],
+        instructions: &[Instruction],
+        inner_instructions: &[(u32, &[Instruction])],
+    ) -> (Message, TransactionStatusMeta) {
+        let message = Message {
+            account_keys: account_keys.iter().map(|key| key.to_vec()).collect(),
+            instructions: instructions
+                .iter()
+                .map(|(program_id_index, data)| CompiledInstruction {
+                    program_id_index: *program_id_index,
+                    accounts: Vec::new(),
+                    data: data.clone(),
+                })
+                .collect(),
+            ..Default::default()
+        };
+        let meta = TransactionStatusMeta {
+            inner_instructions: inner_instructions
+                .iter()
+                .map(|(index, instructions)| InnerInstructions {
+                    index: *index,
+                    instructions: instructions
+                        .iter()
+                        .map(|(program_id_index, data)| InnerInstruction {
+                            program_id_index: *program_id_index,
+                            accounts: Vec::new(),
+                            data: data.clone(),
+                            stack_height: Some(2),
+                        })
+                        .collect(),
+                })
+                .collect(),
+            loaded_writable_addresses: vec![WRITABLE.to_vec()],
+            loaded_readonly_addresses: vec![READONLY.to_vec()],
+            ..Default::default()
+        };
+        (message, meta)
+    }
+
+    #[test]
+    fn resolves_program_ids_across_static_and_loaded_addresses() {
+        // Program id index, the program it resolves to.
+        let cases: &[(u32, Option<&[u8]>)] = &[
+            (0, Some(PROGRAM)),
+            (1, Some(STATIC)),
+            (2, Some(WRITABLE)),
+            (3, Some(READONLY)),
+            (4, None),
+            (u32::MAX, None),
+        ];
+        for (program_id_index, expected) in cases {
+            let (message, meta) =
+                transaction(&[PROGRAM, STATIC], &[(*program_id_index, Vec::new())], &[]);
+            let account_keys = account_keys(&message, &meta);
+            let invoked = invoked_program_ids(&account_keys, &message, &meta);
+            assert_eq!(
+                invoked.first().copied(),
+                *expected,
+                "index {program_id_index}"
+            );
+
+            for program_id in [PROGRAM, WRITABLE, READONLY] {
+                let found = program_instructions(program_id, &account_keys, &message, &meta);
+                assert_eq!(
+                    found.len(),
+                    usize::from(*expected == Some(program_id)),
+                    "index {program_id_index}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn lists_program_instructions_in_execution_order() {
+        // The program is reached through the readonly loaded address, index 3.
+        let (message, meta) = transaction(
+            &[STATIC, PROGRAM],
+            &[(3, vec![1]), (0, vec![2]), (1, vec![3])],
+            &[
+                (1, &[(3, tagged(&[4])), (0, vec![5]), (9, vec![6])]),
+                (0, &[(0, vec![7]), (3, vec![8]), (3, tagged(&[9]))]),
+                (2, &[(1, vec![10])]),
+            ],
+        );
+        let account_keys = account_keys(&message, &meta);
+        let found: Vec<_> = program_instructions(READONLY, &account_keys, &message, &meta)
+            .iter()
+            .map(|instruction| {
+                (
+                    instruction.instruction_index,
+                    instruction.inner_instruction_index,
+                    instruction.data.to_vec(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (0, None, vec![1]),
+                (0, Some(1), vec![8]),
+                (0, Some(2), tagged(&[9])),
+                (1, Some(0), tagged(&[4])),
+            ]
+        );
+    }
+
+    #[test]
+    fn picks_tagged_self_invocations_only() {
+        // Whether the instruction is inner, its data, the event data.
+        type Case = (bool, Vec<u8>, Option<&'static [u8]>);
+        let cases: &[Case] = &[
+            (true, tagged(&[1, 2]), Some(&[1, 2])),
+            (true, tagged(&[]), Some(&[])),
+            // An untagged self-CPI, such as a plain instruction of the program.
+            (true, vec![1, 2], None),
+            (true, EVENT_IX_TAG_LE[..7].to_vec(), None),
+            (true, Vec::new(), None),
+            // A top-level instruction carrying the tag is not an event.
+            (false, tagged(&[1, 2]), None),
+        ];
+        for (inner, data, expected) in cases {
+            let instructions = [ProgramInstruction {
+                instruction_index: 1,
+                inner_instruction_index: inner.then_some(2),
+                data,
+            }];
+            let events: Vec<_> = event_instructions(&instructions)
+                .map(|event| {
+                    (
+                        event.instruction_index,
+                        event.inner_instruction_index,
+                        event.data,
+                    )
+                })
+                .collect();
+            let expected: Vec<_> = expected.iter().map(|data| (1, 2, *data)).collect();
+            assert_eq!(events, expected, "inner {inner}, data {data:?}");
+        }
+    }
+}
@@ -1,173 +1,1900 @@
-mod events;
+// The handler macros pass `params` as a raw pointer to the generated exports.
+#![allow(clippy::not_unsafe_ptr_arg_deref)]
+
+mod decoder;
+pub mod events;
+#[cfg(any(test, feature = "fixtures"))]
+pub mod fixtures;
+mod instructions;
+mod logs;
+#[cfg(any(test, feature = "native"))]
+mod memory_store;
+#[cfg(any(test, feature = "native"))]
+mod native;
+mod params;
+#[allow(dead_code)]
 mod pb;
+mod quarantine;
+#[cfg(any(test, feature = "replay"))]
+mod replay;
+#[cfg(any(test, feature = "replay"))]
+mod schema;
+mod short_code;
+mod tables;
 mod tables_with_incrementing_key;
+mod transaction_error;
+
+// Decoding for services outside of the substreams, with the decoder of
+// `map_gummy_events`.
+pub use decoder::{DecodeError, DecodeOptions, Decoded};
+pub use events::GummyEvent;
+#[cfg(feature = "native")]
+pub use native::NativeModules;
+// The messages `map_gummy_events` outputs, see `proto/event.proto`.
+pub use pb::sf::solana::event::v1 as messages;
+// Replay of the rows of the `Quarantine` table, see `src/bin/replay.rs`.
+#[cfg(feature = "replay")]
+pub use replay::{replay, Replay, COLUMNS as QUARANTINE_COLUMNS};
+// DDL of the tables of events decoded into an `IdlEvent`, see `src/schema.rs`.
+#[cfg(feature = "replay")]
+pub use schema::{idl_tables, Dialect};
 
 use anyhow::Result;
 use base64::prelude::*;
+#[cfg(any(test, feature = "native"))]
+use memory_store::MemoryStore;
+use params::{Params, ProgramId};
+use pb::sf::solana::event::v1::{
+    event, Event, Events, FailedAttempt, Position, Source, TruncatedTransaction,
+};
+use short_code::ShortCode;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use substreams::pb::sf::substreams::index::v1::Keys;
+use substreams::pb::substreams::store_delta::Operation;
+use substreams::pb::substreams::Clock;
+use substreams::scalar::BigInt;
+use substreams::store::{
+    DeltaBigInt, DeltaInt64, DeltaProto, DeltaString, Deltas, StoreAdd, StoreAddBigInt,
+    StoreAddInt64, StoreGet, StoreGetBigInt, StoreGetInt64, StoreGetString, StoreMax,
+    StoreMaxBigInt, StoreMin, StoreMinBigInt, StoreNew, StoreSet, StoreSetIfNotExists,
+    StoreSetIfNotExistsBigInt, StoreSetIfNotExistsInt64, StoreSetIfNotExistsString, StoreSetInt64,
+    StoreSetProto, StoreSetString,
+};
+use substreams::Hex;
+use substreams_database_change::pb::database::DatabaseChanges;
 use substreams_entity_change::pb::entity::EntityChanges;
-use substreams_entity_change::tables::ToValue;
-use substreams_solana::pb::sf::solana::r#type::v1::Block;
+use substreams_solana::pb::sf::solana::r#type::v1::{
+    Block, TokenBalance, Transaction, TransactionStatusMeta,
+};
+use tables::{DatabaseTables, EntityTables, Row, Tables};
 use tables_with_incrementing_key::TablesWithIncrementingKey;
 
-#[substreams::handlers::map]
-fn map_events(block: Block) -> Result<EntityChanges, substreams::errors::Error> {
-    let mut tables = TablesWithIncrementingKey::new();
+/// Decodes the base64 payload of a `Program data:` log line into an event of
+/// the IDL, with the latest layouts and no trailing bytes allowed.
+pub fn decode_program_data(message: &str) -> Result<GummyEvent, DecodeError> {
+    let options = DecodeOptions {
+        slot: u64::MAX,
+        lenient: false,
+    };
+    decode_program_data_with_options(message, &options).map(|decoded| decoded.event)
+}
+
+/// Decodes the base64 payload of a `Program data:` log line as
+/// `map_gummy_events` does for a payload found at `options.slot`.
+pub fn decode_program_data_with_options(
+    message: &str,
+    options: &DecodeOptions,
+) -> Result<Decoded, DecodeError> {
+    decoder::decode_program_data(message, options)
+}
+
+/// Key of the blocks invoking one of the deployments given in `program_ids`,
+/// which `map_gummy_events` filters on.
+const TRACKED_PROGRAM_KEY: &str = "tracked_program";
 
-    let program_id = "6aw4sBovP6yaG1q4y2GpjaQcLZJbBWMJP4aJFsLKxgb3";
-    let log_event_prefix = "Program data: ";
-    let start_log_message = format!("Program {program_id} invoke");
-    let end_log_message = format!("Program {program_id} success");
+/// Keys each block by the programs its transactions invoke, as
+/// `program:<base58 address>`, so modules can skip blocks that do not involve
+/// the staking program. Blocks invoking one of the deployments of the params,
+/// which are those of `map_gummy_events`, are also keyed `tracked_program`, so
+/// the filter follows the params instead of repeating their program ids.
+#[substreams::handlers::map]
+fn index_program_ids(params: String, block: Block) -> Result<Keys, substreams::errors::Error> {
+    let params = Params::parse(&params)?;
+    let mut program_ids = BTreeSet::new();
 
     for tx in block.transactions.iter() {
+        let (Some(transaction), Some(meta)) = (&tx.transaction, &tx.meta) else {
+            continue;
+        };
+        let Some(message) = &transaction.message else {
+            continue;
+        };
+        let account_keys = instructions::account_keys(message, meta);
+        program_ids.extend(instructions::invoked_program_ids(
+            &account_keys,
+            message,
+            meta,
+        ));
+    }
+
+    let tracked = params
+        .program_ids
+        .iter()
+        .any(|program_id| program_ids.contains(program_id.bytes.as_slice()));
+    let mut keys: Vec<_> = program_ids
+        .into_iter()
+        .map(|program_id| format!("program:{}", bs58::encode(program_id).into_string()))
+        .collect();
+    if tracked {
+        keys.push(TRACKED_PROGRAM_KEY.to_string());
+    }
+    Ok(Keys { keys })
+}
+
+#[substreams::handlers::map]
+fn map_gummy_events(params: String, block: Block) -> Result<Events, substreams::errors::Error> {
+    gummy_events(&params, &block)
+}
+
+/// The output of `map_gummy_events`, callable outside of a substreams runtime.
+pub fn gummy_events(params: &str, block: &Block) -> Result<Events, substreams::errors::Error> {
+    let params = Params::parse(params)?;
+    let mut events = Events::default();
+
+    let timestamp = block.block_time.as_ref().map(|x| x.timestamp);
+    let options = DecodeOptions {
+        slot: block.slot,
+        lenient: params.lenient_decoding,
+    };
+
+    for (tx_index, tx) in block.transactions.iter().enumerate() {
         let Some(transaction) = &tx.transaction else {
             continue;
         };
-        let tx_sig = bs58::encode(&transaction.signatures[0]).into_string();
-        tables.set_prefix_and_reset_counters(tx_sig);
+        // Only malformed transactions have no signature.
+        let Some(signature) = transaction.signatures.first() else {
+            continue;
+        };
+        let tx_sig = bs58::encode(signature).into_string();
         let Some(meta) = tx.meta.as_ref() else {
             continue;
         };
+        let tx_error = meta
+            .err
+            .as_ref()
+            .map(|err| transaction_error::describe(&err.err));
+        if tx_error.is_some() && !params.record_failed_transactions {
+            continue;
+        }
+
+        for program_id in params.program_ids.iter() {
+            let location = Event {
+                slot: block.slot,
+                tx_signature: tx_sig.clone(),
+                tx_index: tx_index as u32,
+                timestamp,
+                program_id: program_id.address.clone(),
+                ..Default::default()
+            };
+            collect_program_events(
+                &mut events,
+                program_id,
+                transaction,
+                meta,
+                location,
+                &tx_error,
+                &options,
+            );
+        }
+    }
 
-        let mut in_program = false;
+    Ok(events)
+}
 
-        for log_message in meta.log_messages.iter() {
-            if log_message.starts_with(start_log_message.as_str()) {
-                in_program = true;
-            } else if log_message.starts_with(end_log_message.as_str()) {
-                in_program = false;
-            } else if in_program && log_message.starts_with(log_event_prefix) {
-                let Some(message) = log_message.strip_prefix(log_event_prefix) else {
-                    continue;
-                };
-                //------------------- Parse message to the event ---------------------------------
-                let Ok(base64_decoded_message) = BASE64_STANDARD.decode(message) else {
-                    tables.log_error("Error decoding base64");
-                    continue;
+/// Collects the events `program_id` emitted in a transaction. `location` is
+/// filled with everything but the position of the event within the
+/// transaction.
+fn collect_program_events(
+    events: &mut Events,
+    program_id: &ProgramId,
+    transaction: &Transaction,
+    meta: &TransactionStatusMeta,
+    location: Event,
+    tx_error: &Option<String>,
+    options: &DecodeOptions,
+) {
+    let Some(message) = &transaction.message else {
+        return;
+    };
+    let account_keys = instructions::account_keys(message, meta);
+    if !account_keys.contains(&program_id.bytes.as_slice()) {
+        return;
+    }
+    let first_event = events.events.len();
+    let program_instructions =
+        instructions::program_instructions(&program_id.bytes, &account_keys, message, meta);
+
+    // Events found in the logs, used to skip their `emit_cpi!` duplicates.
+    let mut log_events = Vec::new();
+
+    for program_data in logs::program_data(&meta.log_messages) {
+        if program_data.program_id != program_id.address {
+            continue;
+        }
+        let decoded = decoder::decode_program_data(program_data.data, options);
+        if let Ok(decoded) = &decoded {
+            log_events.push(decoded.event.clone());
+        }
+        let location = Event {
+            log_index: program_data.log_index as u32,
+            source: Source::Log as i32,
+            ..location.clone()
+        };
+        push_decoded(events, location, tx_error, decoded, || {
+            program_data.data.to_string()
+        });
+    }
+
+    if let Some(log_index) = logs::truncation_index(&meta.log_messages) {
+        if !program_instructions.is_empty() {
+            // Invocations are logged in execution order, so the ones past
+            // the logged count are the one running at the cut, if any, and
+            // those that happened after it.
+            let logged = logs::logged_invocation_count(&meta.log_messages, &program_id.address);
+            let unlogged = program_instructions.get(logged..).unwrap_or_default();
+            let (recovered, unrecovered): (Vec<_>, Vec<_>) = unlogged
+                .iter()
+                .partition(|instruction| instruction.event_data().is_some());
+            events.truncated_transactions.push(TruncatedTransaction {
+                slot: location.slot,
+                tx_signature: location.tx_signature.clone(),
+                tx_index: location.tx_index,
+                timestamp: location.timestamp,
+                log_index: log_index as u32,
+                unlogged_invocations: unlogged.len() as u32,
+                recovered_events: recovered.len() as u32,
+                unrecovered_instructions: unrecovered
+                    .iter()
+                    .map(|instruction| {
+                        Hex(&instruction.data[..instruction.data.len().min(8)]).to_string()
+                    })
+                    .collect(),
+                program_id: location.program_id.clone(),
+            });
+        }
+    }
+
+    for instruction in instructions::event_instructions(&program_instructions) {
+        let decoded = decoder::decode_event_instruction_data(instruction.data, options);
+        if let Ok(decoded) = &decoded {
+            if let Some(position) = log_events.iter().position(|e| *e == decoded.event) {
+                log_events.swap_remove(position);
+                continue;
+            }
+        }
+        let location = Event {
+            source: Source::InnerInstruction as i32,
+            instruction_index: instruction.instruction_index,
+            inner_instruction_index: instruction.inner_instruction_index,
+            ..location.clone()
+        };
+        push_decoded(events, location, tx_error, decoded, || {
+            BASE64_STANDARD.encode(instruction.data)
+        });
+    }
+
+    // What the user received tells the withdrawn amount of positions opened
+    // before the first block indexed, which the stores know nothing about.
+    let new_events = &mut events.events[first_event..];
+    let withdrawing_users: Vec<_> = new_events
+        .iter()
+        .filter_map(|event| event.event.as_ref()?.withdrawing_user().map(str::to_string))
+        .collect();
+    for event in new_events.iter_mut() {
+        let Some(user) = event.event.as_ref().and_then(|e| e.withdrawing_user()) else {
+            continue;
+        };
+        if withdrawing_users.iter().filter(|u| *u == user).count() == 1 {
+            event.token_balance_increase = token_balance_increase(meta, user);
+        }
+    }
+}
+
+/// What the token accounts of `owner` gained in a transaction, if they gained
+/// a single mint.
+fn token_balance_increase(meta: &TransactionStatusMeta, owner: &str) -> Option<u64> {
+    let amount = |balance: &TokenBalance| -> i128 {
+        balance
+            .ui_token_amount
+            .as_ref()
+            .and_then(|amount| amount.amount.parse().ok())
+            .unwrap_or(0)
+    };
+    // Net change of each mint, accounts missing from the pre or post balances
+    // being created or closed by the transaction.
+    let mut changes: BTreeMap<&str, i128> = BTreeMap::new();
+    for balance in meta.post_token_balances.iter() {
+        if balance.owner == owner {
+            *changes.entry(&balance.mint).or_default() += amount(balance);
+        }
+    }
+    for balance in meta.pre_token_balances.iter() {
+        if balance.owner == owner {
+            *changes.entry(&balance.mint).or_default() -= amount(balance);
+        }
+    }
+    let mut increases = changes.into_values().filter(|change| *change > 0);
+    match (increases.next(), increases.next()) {
+        (Some(increase), None) => u64::try_from(increase).ok(),
+        _ => None,
+    }
+}
+
+/// Files a decoding result found at `location` as an event, a failed attempt
+/// or a decoding error. `payload` gives the raw data kept with errors.
+fn push_decoded(
+    events: &mut Events,
+    location: Event,
+    tx_error: &Option<String>,
+    decoded: Result<Decoded, decoder::DecodeError>,
+    payload: impl FnOnce() -> String,
+) {
+    match decoded {
+        Ok(decoded) => {
+            let event = Event {
+                layout_version: decoded.layout_version,
+                ignored_bytes: decoded.ignored_bytes as u32,
+                event: Some(decoded.event.into()),
+                ..location
+            };
+            match tx_error {
+                None => events.events.push(event),
+                Some(error) => events.failed_attempts.push(FailedAttempt {
+                    event: Some(event),
+                    error: error.clone(),
+                }),
+            }
+        }
+        Err(error) => {
+            let (message, trailing_bytes) = match &error {
+                decoder::DecodeError::BorshDecode { message, .. } => (Some(message.clone()), 0),
+                decoder::DecodeError::TrailingBytes { count, .. } => (None, *count as u32),
+                _ => (None, 0),
+            };
+            events.errors.push(pb::sf::solana::event::v1::DecodeError {
+                slot: location.slot,
+                tx_signature: location.tx_signature,
+                tx_index: location.tx_index,
+                log_index: location.log_index,
+                description: error.to_string(),
+                source: location.source,
+                instruction_index: location.instruction_index,
+                inner_instruction_index: location.inner_instruction_index,
+                program_id: location.program_id,
+                kind: error.kind() as i32,
+                event: error.event().map(str::to_string),
+                discriminator: error
+                    .discriminator()
+                    .map(|discriminator| Hex(discriminator).to_string()),
+                payload: payload(),
+                message,
+                trailing_bytes,
+                timestamp: location.timestamp,
+                tx_error: tx_error.clone(),
+            })
+        }
+    }
+}
+
+/// Key of a store entry scoped to a deployment, e.g. a staker's position.
+fn store_key(program_id: &str, key: &str) -> String {
+    format!("{program_id}:{key}")
+}
+
+#[substreams::handlers::store]
+fn store_deposit_counts(events: Events, store: StoreAddInt64) {
+    add_deposit_counts(&events, &store);
+}
+
+fn add_deposit_counts(events: &Events, store: &impl StoreAdd<i64>) {
+    for (ordinal, event) in events.events.iter().enumerate() {
+        if let Some(event::Event::Deposit(deposit)) = &event.event {
+            store.add(
+                ordinal as u64,
+                store_key(&event.program_id, &deposit.user),
+                1,
+            );
+        }
+    }
+}
+
+#[substreams::handlers::store]
+fn store_first_deposit_slots(events: Events, store: StoreSetIfNotExistsInt64) {
+    set_first_deposit_slots(&events, &store);
+}
+
+fn set_first_deposit_slots(events: &Events, store: &impl StoreSetIfNotExists<i64>) {
+    for (ordinal, event) in events.events.iter().enumerate() {
+        if let Some(event::Event::Deposit(deposit)) = &event.event {
+            store.set_if_not_exists(
+                ordinal as u64,
+                store_key(&event.program_id, &deposit.user),
+                &(event.slot as i64),
+            );
+        }
+    }
+}
+
+#[substreams::handlers::store]
+fn store_lock_expirations(events: Events, store: StoreSetInt64) {
+    set_lock_expirations(&events, &store);
+}
+
+fn set_lock_expirations(events: &Events, store: &impl StoreSet<i64>) {
+    for (ordinal, event) in events.events.iter().enumerate() {
+        if let Some(event::Event::Deposit(deposit)) = &event.event {
+            store.set(
+                ordinal as u64,
+                store_key(&event.program_id, &deposit.user),
+                &(deposit.lock_expires as i64),
+            );
+        }
+    }
+}
+
+/// Keeps the position of every staker. Each position is written once per
+/// block, with its state after the last event touching it, so it yields a
+/// single `UserPosition` change.
+#[substreams::handlers::store]
+fn store_positions(
+    events: Events,
+    deposit_counts: StoreGetInt64,
+    first_deposit_slots: StoreGetInt64,
+    lock_expirations: StoreGetInt64,
+    store: StoreSetProto<Position>,
+) {
+    set_positions(
+        &events,
+        &deposit_counts,
+        &first_deposit_slots,
+        &lock_expirations,
+        &store,
+    );
+}
+
+fn set_positions(
+    events: &Events,
+    deposit_counts: &impl StoreGet<i64>,
+    first_deposit_slots: &impl StoreGet<i64>,
+    lock_expirations: &impl StoreGet<i64>,
+    store: &impl StoreSet<Position>,
+) {
+    let mut latest = BTreeMap::new();
+    for (ordinal, event) in events.events.iter().enumerate() {
+        let Some((user, total_amount)) = event.event.as_ref().and_then(|e| e.staked_total()) else {
+            continue;
+        };
+        latest.insert(
+            store_key(&event.program_id, user),
+            (ordinal as u64, event, user, total_amount),
+        );
+    }
+
+    let mut latest: Vec<_> = latest.into_iter().collect();
+    latest.sort_by_key(|(_, (ordinal, ..))| *ordinal);
+    for (key, (ordinal, event, user, total_amount)) in latest {
+        let position = Position {
+            program_id: event.program_id.clone(),
+            user: user.to_string(),
+            total_amount,
+            lock_expires: lock_expirations.get_last(&key).unwrap_or_default() as u32,
+            first_deposit_slot: first_deposit_slots.get_last(&key).map(|slot| slot as u64),
+            last_activity_slot: event.slot,
+            deposit_count: deposit_counts.get_last(&key).unwrap_or_default() as u64,
+        };
+        store.set(ordinal, key, &position);
+    }
+}
+
+/// Current referrer of every user, as set by `SetReferrer`.
+#[substreams::handlers::store]
+fn store_referrers(events: Events, store: StoreSetString) {
+    set_referrers(&events, &store);
+}
+
+fn set_referrers(events: &Events, store: &impl StoreSet<String>) {
+    for (ordinal, event) in events.events.iter().enumerate() {
+        if let Some(event::Event::SetReferrer(set_referrer)) = &event.event {
+            store.set(
+                ordinal as u64,
+                store_key(&event.program_id, &set_referrer.user),
+                &set_referrer.new_referrer,
+            );
+        }
+    }
+}
+
+/// The short code an event changes, and the full address it points to after
+/// it, empty for a deletion.
+fn short_referrer_change(event: &Event) -> Option<(&[u8], &str)> {
+    match &event.event {
+        Some(event::Event::RegisterShortReferrer(register)) => {
+            Some((&register.short, &register.full))
+        }
+        Some(event::Event::AdminRegisterShortReferrer(register)) => {
+            Some((&register.short, &register.full))
+        }
+        Some(event::Event::AdminDeleteShortReferrer(delete)) => Some((&delete.short, "")),
+        _ => None,
+    }
+}
+
+/// Full address each short code points to. Deleted codes are set to an
+/// empty string, as deleting by prefix would also drop longer codes.
+#[substreams::handlers::store]
+fn store_short_referrers(events: Events, store: StoreSetString) {
+    set_short_referrers(&events, &store);
+}
+
+fn set_short_referrers(events: &Events, store: &impl StoreSet<String>) {
+    for (ordinal, event) in events.events.iter().enumerate() {
+        let Some((short, full)) = short_referrer_change(event) else {
+            continue;
+        };
+        store.set(
+            ordinal as u64,
+            store_key(&event.program_id, &ShortCode::new(short).hex),
+            &full.to_string(),
+        );
+    }
+}
+
+/// Slot of the last registration of each code to a full address. Its deltas
+/// tell whether the pair already has a `ReferrerShortCode` row.
+#[substreams::handlers::store]
+fn store_referrer_short_codes(events: Events, store: StoreSetInt64) {
+    set_referrer_short_codes(&events, &store);
+}
+
+fn set_referrer_short_codes(events: &Events, store: &impl StoreSet<i64>) {
+    for (ordinal, event) in events.events.iter().enumerate() {
+        let Some((short, full)) = short_referrer_change(event).filter(|(_, full)| !full.is_empty())
+        else {
+            continue;
+        };
+        store.set(
+            ordinal as u64,
+            store_key(
+                &event.program_id,
+                &referrer_short_code_key(full, &ShortCode::new(short)),
+            ),
+            &(event.slot as i64),
+        );
+    }
+}
+
+/// Hex form of the first code registered with each display form, to flag the
+/// codes that read like an earlier one.
+#[substreams::handlers::store]
+fn store_short_code_displays(events: Events, store: StoreSetIfNotExistsString) {
+    set_short_code_displays(&events, &store);
+}
+
+fn set_short_code_displays(events: &Events, store: &impl StoreSetIfNotExists<String>) {
+    for (ordinal, event) in events.events.iter().enumerate() {
+        let Some((short, full)) = short_referrer_change(event) else {
+            continue;
+        };
+        if full.is_empty() {
+            continue;
+        }
+        let short = ShortCode::new(short);
+        store.set_if_not_exists(
+            ordinal as u64,
+            store_key(&event.program_id, &short.display),
+            &short.hex,
+        );
+    }
+}
+
+/// Total value locked per deployment, as the sum of the positions seen so
+/// far. A position opened before the initial block only counts once it is
+/// first touched, since its earlier total is unknown.
+#[substreams::handlers::store]
+fn store_tvl(positions: Deltas<DeltaProto<Position>>, store: StoreAddBigInt) {
+    add_tvl(&positions, &store);
+}
+
+fn add_tvl(positions: &Deltas<DeltaProto<Position>>, store: &impl StoreAdd<BigInt>) {
+    for delta in positions.deltas.iter() {
+        let change =
+            BigInt::from(delta.new_value.total_amount) - BigInt::from(delta.old_value.total_amount);
+        store.add(delta.ordinal, &delta.new_value.program_id, change);
+    }
+}
+
+/// Start of the UTC day the block belongs to, in seconds.
+fn day_start(clock: &Clock) -> i64 {
+    let timestamp = clock.timestamp.as_ref().map_or(0, |t| t.seconds);
+    // Saturates for timestamps before the first day `i64` can hold.
+    timestamp.saturating_sub(timestamp.rem_euclid(86400))
+}
+
+/// Key of a deployment's daily TVL snapshot in the stores.
+fn day_key(program_id: &str, clock: &Clock) -> String {
+    format!("{program_id}:{}", day_start(clock))
+}
+
+/// TVL when the first change of the day happened.
+#[substreams::handlers::store]
+fn store_daily_tvl_open(clock: Clock, tvl: Deltas<DeltaBigInt>, store: StoreSetIfNotExistsBigInt) {
+    set_daily_tvl_open(&clock, &tvl, &store);
+}
+
+fn set_daily_tvl_open(
+    clock: &Clock,
+    tvl: &Deltas<DeltaBigInt>,
+    store: &impl StoreSetIfNotExists<BigInt>,
+) {
+    for delta in tvl.deltas.iter() {
+        store.set_if_not_exists(delta.ordinal, day_key(&delta.key, clock), &delta.old_value);
+    }
+}
+
+/// TVL of each deployment before the block, at ordinal 0, then after each
+/// event changing a staked total, at the ordinal of the event, so that the
+/// daily high and low see the values reached within a block.
+fn running_tvl(
+    events: &Events,
+    positions: &Deltas<DeltaProto<Position>>,
+    tvl: &Deltas<DeltaBigInt>,
+) -> Vec<(u64, String, BigInt)> {
+    // The first delta of a key holds the value from before the block.
+    let mut running: BTreeMap<&str, BigInt> = BTreeMap::new();
+    for delta in tvl.deltas.iter() {
+        running
+            .entry(delta.key.as_str())
+            .or_insert_with(|| delta.old_value.clone());
+    }
+    let mut staked_totals: HashMap<&str, u64> = HashMap::new();
+    for delta in positions.deltas.iter() {
+        staked_totals
+            .entry(delta.key.as_str())
+            .or_insert(delta.old_value.total_amount);
+    }
+
+    let mut values: Vec<_> = running
+        .iter()
+        .map(|(program_id, tvl)| (0, program_id.to_string(), tvl.clone()))
+        .collect();
+    for (ordinal, event) in events.events.iter().enumerate() {
+        let Some((user, total_amount)) = event.event.as_ref().and_then(|e| e.staked_total()) else {
+            continue;
+        };
+        let key = store_key(&event.program_id, user);
+        let (Some(previous), Some(tvl)) = (
+            staked_totals.get_mut(key.as_str()),
+            running.get_mut(event.program_id.as_str()),
+        ) else {
+            continue;
+        };
+        *tvl = tvl.clone() + BigInt::from(total_amount) - BigInt::from(*previous);
+        *previous = total_amount;
+        values.push((ordinal as u64, event.program_id.clone(), tvl.clone()));
+    }
+    values
+}
+
+#[substreams::handlers::store]
+fn store_daily_tvl_high(
+    clock: Clock,
+    events: Events,
+    positions: Deltas<DeltaProto<Position>>,
+    tvl: Deltas<DeltaBigInt>,
+    store: StoreMaxBigInt,
+) {
+    max_daily_tvl(&clock, &events, &positions, &tvl, &store);
+}
+
+fn max_daily_tvl(
+    clock: &Clock,
+    events: &Events,
+    positions: &Deltas<DeltaProto<Position>>,
+    tvl: &Deltas<DeltaBigInt>,
+    store: &impl StoreMax<BigInt>,
+) {
+    for (ordinal, program_id, value) in running_tvl(events, positions, tvl) {
+        store.max(ordinal, day_key(&program_id, clock), value);
+    }
+}
+
+#[substreams::handlers::store]
+fn store_daily_tvl_low(
+    clock: Clock,
+    events: Events,
+    positions: Deltas<DeltaProto<Position>>,
+    tvl: Deltas<DeltaBigInt>,
+    store: StoreMinBigInt,
+) {
+    min_daily_tvl(&clock, &events, &positions, &tvl, &store);
+}
+
+fn min_daily_tvl(
+    clock: &Clock,
+    events: &Events,
+    positions: &Deltas<DeltaProto<Position>>,
+    tvl: &Deltas<DeltaBigInt>,
+    store: &impl StoreMin<BigInt>,
+) {
+    for (ordinal, program_id, value) in running_tvl(events, positions, tvl) {
+        store.min(ordinal, day_key(&program_id, clock), value);
+    }
+}
+
+#[substreams::handlers::map]
+fn map_events(
+    clock: Clock,
+    events: Events,
+    positions: Deltas<DeltaProto<Position>>,
+    referrers: Deltas<DeltaString>,
+    short_referrers: Deltas<DeltaString>,
+    referrer_short_codes: Deltas<DeltaInt64>,
+    short_code_displays: StoreGetString,
+    tvl: Deltas<DeltaBigInt>,
+    daily_tvl_open: Deltas<DeltaBigInt>,
+    daily_tvl_high: StoreGetBigInt,
+    daily_tvl_low: StoreGetBigInt,
+) -> Result<EntityChanges, substreams::errors::Error> {
+    Ok(entity_changes(
+        &clock,
+        &events,
+        &positions,
+        &referrers,
+        &short_referrers,
+        &referrer_short_codes,
+        &short_code_displays,
+        &tvl,
+        &daily_tvl_open,
+        &daily_tvl_high,
+        &daily_tvl_low,
+    ))
+}
+
+#[allow(clippy::too_many_arguments)]
+fn entity_changes(
+    clock: &Clock,
+    events: &Events,
+    positions: &Deltas<DeltaProto<Position>>,
+    referrers: &Deltas<DeltaString>,
+    short_referrers: &Deltas<DeltaString>,
+    referrer_short_codes: &Deltas<DeltaInt64>,
+    short_code_displays: &impl StoreGet<String>,
+    tvl: &Deltas<DeltaBigInt>,
+    daily_tvl_open: &Deltas<DeltaBigInt>,
+    daily_tvl_high: &impl StoreGet<BigInt>,
+    daily_tvl_low: &impl StoreGet<BigInt>,
+) -> EntityChanges {
+    let mut tables = TablesWithIncrementingKey::<EntityTables>::new();
+    build_tables(
+        &mut tables,
+        events,
+        positions,
+        referrers,
+        short_referrers,
+        referrer_short_codes,
+        short_code_displays,
+    );
+    build_tvl_tables(
+        &mut tables.tables,
+        clock,
+        tvl,
+        daily_tvl_open,
+        daily_tvl_high,
+        daily_tvl_low,
+    );
+    tables.to_entity_changes()
+}
+
+/// The output of `map_events` while the stores are empty, callable outside of
+/// a substreams runtime. Tables built from store deltas, `UserPosition` and
+/// the TVL ones, are left out: `NativeModules` runs the stores as well.
+#[cfg(any(test, feature = "native"))]
+pub fn entity_changes_without_stores(events: &Events) -> EntityChanges {
+    let mut tables = TablesWithIncrementingKey::<EntityTables>::new();
+    build_tables(
+        &mut tables,
+        events,
+        &Deltas::new(Vec::new()),
+        &Deltas::new(Vec::new()),
+        &Deltas::new(Vec::new()),
+        &Deltas::new(Vec::new()),
+        &MemoryStore::default(),
+    );
+    tables.to_entity_changes()
+}
+
+#[substreams::handlers::map]
+fn db_out(
+    clock: Clock,
+    events: Events,
+    positions: Deltas<DeltaProto<Position>>,
+    referrers: Deltas<DeltaString>,
+    short_referrers: Deltas<DeltaString>,
+    referrer_short_codes: Deltas<DeltaInt64>,
+    short_code_displays: StoreGetString,
+    tvl: Deltas<DeltaBigInt>,
+    daily_tvl_open: Deltas<DeltaBigInt>,
+    daily_tvl_high: StoreGetBigInt,
+    daily_tvl_low: StoreGetBigInt,
+) -> Result<DatabaseChanges, substreams::errors::Error> {
+    let mut tables = TablesWithIncrementingKey::<DatabaseTables>::new();
+    build_tables(
+        &mut tables,
+        &events,
+        &positions,
+        &referrers,
+        &short_referrers,
+        &referrer_short_codes,
+        &short_code_displays,
+    );
+    build_tvl_tables(
+        &mut tables.tables,
+        &clock,
+        &tvl,
+        &daily_tvl_open,
+        &daily_tvl_high,
+        &daily_tvl_low,
+    );
+    Ok(tables.to_database_changes())
+}
+
+/// Writes the TVL of every deployment that changed in the block, and updates
+/// its snapshot for the day.
+fn build_tvl_tables<T: Tables>(
+    tables: &mut T,
+    clock: &Clock,
+    tvl: &Deltas<DeltaBigInt>,
+    daily_tvl_open: &Deltas<DeltaBigInt>,
+    daily_tvl_high: &impl StoreGet<BigInt>,
+    daily_tvl_low: &impl StoreGet<BigInt>,
+) {
+    // Deltas come in ordinal order, the last one holds the end of block TVL.
+    let mut closes = BTreeMap::new();
+    for delta in tvl.deltas.iter() {
+        closes.insert(delta.key.as_str(), &delta.new_value);
+    }
+
+    let timestamp = clock.timestamp.as_ref().map(|t| t.seconds);
+    let day_start = day_start(clock);
+    for (program_id, close) in closes {
+        tables
+            .create_row(
+                "TotalValueLocked",
+                &program_key(program_id, &clock.number.to_string()),
+            )
+            .set("program_id", program_id)
+            .set("slot", clock.number)
+            .set_if_some("timestamp", timestamp)
+            .set("tvl", close);
+
+        let key = day_key(program_id, clock);
+        let row_key = program_key(program_id, &day_start.to_string());
+        let high = daily_tvl_high.get_last(&key).unwrap_or_default();
+        let low = daily_tvl_low.get_last(&key).unwrap_or_default();
+        let row = match daily_tvl_open.deltas.iter().find(|delta| delta.key == key) {
+            Some(open) => tables
+                .create_row("DailyTotalValueLocked", &row_key)
+                .set("program_id", program_id)
+                .set("day_start", day_start)
+                .set("open", &open.new_value),
+            None => tables.update_row("DailyTotalValueLocked", &row_key),
+        };
+        row.set("high", &high).set("low", &low).set("close", close);
+    }
+}
+
+fn build_tables<T: Tables>(
+    tables: &mut TablesWithIncrementingKey<T>,
+    events: &Events,
+    positions: &Deltas<DeltaProto<Position>>,
+    referrers: &Deltas<DeltaString>,
+    short_referrers: &Deltas<DeltaString>,
+    referrer_short_codes: &Deltas<DeltaInt64>,
+    short_code_displays: &impl StoreGet<String>,
+) {
+    // Staked total of each position before the event being processed, `None`
+    // while unknown: positions created in this block had no stored total.
+    let mut staked_totals: HashMap<&str, Option<u64>> = positions
+        .deltas
+        .iter()
+        .map(|delta| {
+            let previous = match delta.operation {
+                Operation::Create => None,
+                _ => Some(delta.old_value.total_amount),
+            };
+            (delta.key.as_str(), previous)
+        })
+        .collect();
+
+    // Referrer of each user before the event being processed, `None` while
+    // unknown. The first delta of a key holds the value from before the block.
+    let mut stored_referrers: HashMap<&str, Option<&str>> = HashMap::new();
+    for delta in referrers.deltas.iter() {
+        stored_referrers
+            .entry(delta.key.as_str())
+            .or_insert(match delta.operation {
+                Operation::Create => None,
+                _ => Some(delta.old_value.as_str()),
+            });
+    }
+
+    // Full address of each short code before the event being processed.
+    let mut stored_short_referrers: HashMap<&str, Option<&str>> = HashMap::new();
+    for delta in short_referrers.deltas.iter() {
+        stored_short_referrers
+            .entry(delta.key.as_str())
+            .or_insert(Some(delta.old_value.as_str()).filter(|full| !full.is_empty()));
+    }
+
+    // Pairs of full address and short code registered before the block, which
+    // have a `ReferrerShortCode` row already.
+    let mut stored_referrer_short_codes = HashSet::new();
+    for delta in referrer_short_codes.deltas.iter() {
+        if delta.operation != Operation::Create {
+            stored_referrer_short_codes.insert(delta.key.as_str());
+        }
+    }
+
+    for event in events.events.iter() {
+        if tables.prefix() != event.tx_signature {
+            tables.set_prefix_and_reset_counters(event.tx_signature.clone());
+        }
+        if event.ignored_bytes > 0 {
+            tables
+                .create_row_with_incrementing_key("IgnoredTrailingBytes")
+                .set("program_id", &event.program_id)
+                .set("event", event.event.as_ref().map_or("", |e| e.name()))
+                .set("layout_version", event.layout_version)
+                .set("ignored_bytes", event.ignored_bytes)
+                .set("slot", event.slot)
+                .set("tx_signature", &event.tx_signature);
+        }
+        let mut withdrawn_amount = None;
+        if let Some((user, total_amount)) = event.event.as_ref().and_then(|e| e.staked_total()) {
+            let key = store_key(&event.program_id, user);
+            if let Some(previous) = staked_totals.get_mut(key.as_str()) {
+                withdrawn_amount = previous.and_then(|previous| previous.checked_sub(total_amount));
+                *previous = Some(total_amount);
+            }
+        }
+        // Positions the stores learnt of with this block, fall back on the
+        // token balances.
+        let withdrawn_amount = withdrawn_amount.or(event.token_balance_increase);
+        match &event.event {
+            // The columns of the event fields are those of the generated
+            // `set_columns`, the other columns come from the stores.
+            Some(event::Event::Deposit(deposit)) => {
+                let row = tables
+                    .create_row_with_incrementing_key("DepositEvent")
+                    .set("program_id", &event.program_id)
+                    .set_if_some("timestamp", event.timestamp);
+                deposit.set_columns(row);
+            }
+            Some(event::Event::Withdraw(withdraw)) => {
+                let row = tables
+                    .create_row_with_incrementing_key("WithdrawEvent")
+                    .set("program_id", &event.program_id)
+                    .set_if_some("timestamp", event.timestamp);
+                withdraw
+                    .set_columns(row)
+                    .set_if_some("withdrawn_amount", withdrawn_amount)
+                    .set("emergency", false);
+            }
+            Some(event::Event::SetReferrer(set_referrer)) => {
+                let stored_referrer = stored_referrers
+                    .get_mut(store_key(&event.program_id, &set_referrer.user).as_str())
+                    .and_then(|stored| stored.replace(&set_referrer.new_referrer));
+
+                // Only the first referrer of a user creates its row, be it in
+                // this block or an earlier one.
+                let key = program_key(&event.program_id, &set_referrer.user);
+                let row = match stored_referrer {
+                    Some(_) => tables.tables.update_row("Referrer", &key),
+                    None => tables.tables.create_row("Referrer", &key),
                 };
-                if base64_decoded_message.len() < 8 {
-                    tables.log_error("Decoded message too short");
-                    continue;
+                row.set("program_id", &event.program_id)
+                    .set("user", &set_referrer.user)
+                    .set("referrer", &set_referrer.new_referrer);
+
+                // A mismatch means an earlier change was missed or misread.
+                let row = tables
+                    .create_row_with_incrementing_key("ReferrerChange")
+                    .set("program_id", &event.program_id);
+                set_referrer
+                    .set_columns(row)
+                    .set("slot", event.slot)
+                    .set_if_some("timestamp", event.timestamp)
+                    .set("tx_signature", &event.tx_signature)
+                    .set_if_some("stored_referrer", stored_referrer)
+                    .set_if_some(
+                        "old_referrer_mismatch",
+                        stored_referrer.map(|stored| stored != set_referrer.old_referrer),
+                    );
+            }
+            Some(event::Event::RegisterShortReferrer(register)) => {
+                register_short_referrer(
+                    tables,
+                    &mut stored_short_referrers,
+                    &stored_referrer_short_codes,
+                    event,
+                    &short_code(event, &register.short, short_code_displays),
+                    &register.full,
+                    None,
+                );
+            }
+            Some(event::Event::AdminRegisterShortReferrer(register)) => {
+                let short = short_code(event, &register.short, short_code_displays);
+                register_short_referrer(
+                    tables,
+                    &mut stored_short_referrers,
+                    &stored_referrer_short_codes,
+                    event,
+                    &short,
+                    &register.full,
+                    Some(&register.initiator),
+                );
+                let row = create_admin_action_row(tables, event, &register.initiator)
+                    .set("user", &register.full);
+                set_short_code(row, &short);
+            }
+            Some(event::Event::AdminDeleteShortReferrer(delete)) => {
+                let short = short_code(event, &delete.short, short_code_displays);
+                let full = stored_short_referrers
+                    .get_mut(store_key(&event.program_id, &short.hex).as_str())
+                    .and_then(|stored| stored.take());
+                tables.delete_row("ShortReferrer", &program_key(&event.program_id, &short.hex));
+                create_short_referrer_registration_row(
+                    tables,
+                    event,
+                    &short,
+                    Some(&delete.initiator),
+                )
+                .set_if_some("full", full);
+                if let Some(full) = full {
+                    deactivate_referrer_short_code(tables, event, full, &short);
                 }
-                let discriminator = &base64_decoded_message[0..8];
-                let serialized_event = &base64_decoded_message[8..];
-
-                match discriminator {
-                    events::DISCRIMINATOR_DEPOSIT => {
-                        let event_name = "Deposit";
-                        match borsh::from_slice::<events::Deposit>(serialized_event) {
-                            Err(e) => tables.log_error(&format!("Error deserializing event '{event_name}': '{e}'. Log is {message}.")),
-                            Ok(event) => {
-                                tables
-                                    .create_row_with_incrementing_key(&format!("{event_name}Event"))
-                                    .set_if_some(
-                                        "timestamp",
-                                        block.block_time.as_ref().map(|x| x.timestamp),
-                                    )
-                                    .set("user", event.user.to_string())
-                                    .set("amount", event.amount)
-                                    .set("total_amount", event.total_amount)
-                                    .set("lock_expires", event.lock_expires)
-                                    .set("referrer", event.referrer.to_string());
-                            }
-                        }
-                    }
-                    events::DISCRIMINATOR_WITHDRAW => {
-                        let event_name = "Withdraw";
-                        match borsh::from_slice::<events::Withdraw>(serialized_event) {
-                            Err(e) => tables.log_error(&format!("Error deserializing event '{event_name}': '{e}'. Log is {message}.")),
-                            Ok(event) => {
-                                tables
-                                    .create_row_with_incrementing_key(&format!("{event_name}Event"))
-                                    .set_if_some(
-                                        "timestamp",
-                                        block.block_time.as_ref().map(|x| x.timestamp),
-                                    )
-                                    .set("user", event.user.to_string())
-                                    .set("total_amount", event.total_amount);
-                            }
-                        }
-                    }
-                    events::DISCRIMINATOR_SET_REFERRER => {
-                        let event_name = "SetReferrer";
-                        match borsh::from_slice::<events::SetReferrer>(serialized_event) {
-                            Err(e) => tables.log_error(&format!("Error deserializing event '{event_name}': '{e}'. Log is {message}.")),
-                            Ok(event) => {
-                                tables
-                                .tables
-                                    .create_row("Referrer", event.user.to_string())
-                                    .set("referrer", event.new_referrer.to_string());
-                            }
-                        }
-                    }
-                    events::DISCRIMINATOR_REGISTER_SHORT_REFERRER => {
-                        let event_name = "RegisterShortReferrer";
-                        match borsh::from_slice::<events::RegisterShortReferrer>(serialized_event) {
-                            Err(e) => tables.log_error(&format!("Error deserializing event '{event_name}': '{e}'. Log is {message}.")),
-                            Ok(event) => {
-                                tables
-                                .tables
-                                    .create_row("ShortReferrer", String::from_utf8_lossy(event.short.as_ref()))
-                                    .set("full", event.full.to_string());
-                            }
-                        }
-                    }
-                    events::DISCRIMINATOR_ADMIN_REGISTER_SHORT_REFERRER => {
-                        let event_name = "AdminRegisterShortReferrer";
-                        match borsh::from_slice::<events::AdminRegisterShortReferrer>(serialized_event) {
-                            Err(e) => tables.log_error(&format!("Error deserializing event '{event_name}': '{e}'. Log is {message}.")),
-                            Ok(event) => {
-                                tables
-                                .tables
-                                    .create_row("ShortReferrer", String::from_utf8_lossy(event.short.as_ref()))
-                                    .set("full", event.full.to_string());
-                            }
-                        }
-                    }
-                    events::DISCRIMINATOR_ADMIN_DELETE_SHORT_REFERRER => {
-                        let event_name = "AdminDeleteShortReferrer";
-                        match borsh::from_slice::<events::AdminDeleteShortReferrer>(serialized_event) {
-                            Err(e) => tables.log_error(&format!("Error deserializing event '{event_name}': '{e}'. Log is {message}.")),
-                            Ok(event) => {
-                                tables
-                                .tables
-                                    .delete_row("ShortReferrer", String::from_utf8_lossy(event.short.as_ref()));
-                            }
-                        }
-                    }
-                    events::DISCRIMINATOR_ADMIN_EMERGENCY_WITHDRAW => {
-                        match borsh::from_slice::<events::AdminEmergencyWithdraw>(serialized_event) {
-                            Err(e) => tables.log_error(&format!("Error deserializing event 'AdminEmergencyWithdraw': '{e}'. Log is {message}.")),
-                            Ok(event) => {
-                                tables
-                                    .create_row_with_incrementing_key(&format!("WithdrawEvent"))
-                                    .set_if_some(
-                                        "timestamp",
-                                        block.block_time.as_ref().map(|x| x.timestamp),
-                                    )
-                                    .set("user", event.user.to_string())
-                                    .set("total_amount", event.total_amount);
-                            }
-                        }
-                    }
-                    _ => {
-                        tables.log_error("Discriminator does not match known events");
-                    }
+                set_short_code(
+                    create_admin_action_row(tables, event, &delete.initiator),
+                    &short,
+                );
+            }
+            Some(event::Event::AdminEmergencyWithdraw(withdraw)) => {
+                let row = tables
+                    .create_row_with_incrementing_key("WithdrawEvent")
+                    .set("program_id", &event.program_id)
+                    .set_if_some("timestamp", event.timestamp);
+                withdraw
+                    .set_columns(row)
+                    .set_if_some("withdrawn_amount", withdrawn_amount)
+                    .set("emergency", true);
+                create_admin_action_row(tables, event, &withdraw.initiator)
+                    .set("user", &withdraw.user)
+                    .set_if_some("amount", withdrawn_amount);
+            }
+            Some(event::Event::IdlEvent(idl_event)) => {
+                let row = tables
+                    .create_row_with_incrementing_key(&idl_event.name)
+                    .set("program_id", &event.program_id)
+                    .set("slot", event.slot)
+                    .set_if_some("timestamp", event.timestamp)
+                    .set("tx_signature", &event.tx_signature);
+                if let Err(e) = events::set_event_columns(&idl_event.name, &idl_event.data, row) {
+                    tables
+                        .log_error(&format!("Error writing event '{}': '{e}'", idl_event.name))
+                        .set("program_id", &event.program_id)
+                        .set("slot", event.slot)
+                        .set("tx_signature", &event.tx_signature);
                 }
             }
+            None => {}
         }
     }
 
-    Ok(tables.to_entity_changes())
+    for failed_attempt in events.failed_attempts.iter() {
+        let Some(event) = &failed_attempt.event else {
+            continue;
+        };
+        if tables.prefix() != event.tx_signature {
+            tables.set_prefix_and_reset_counters(event.tx_signature.clone());
+        }
+        let row = tables
+            .create_row_with_incrementing_key("FailedAttempt")
+            .set("program_id", &event.program_id)
+            .set_if_some("timestamp", event.timestamp)
+            .set("error", &failed_attempt.error);
+        if let Some(event) = &event.event {
+            row.set("event", event.name())
+                .set_if_some("user", event.user());
+        }
+    }
+
+    for truncated in events.truncated_transactions.iter() {
+        tables
+            .tables
+            .create_row(
+                "TruncatedTransaction",
+                &program_key(&truncated.program_id, &truncated.tx_signature),
+            )
+            .set("program_id", &truncated.program_id)
+            .set("tx_signature", &truncated.tx_signature)
+            .set("slot", truncated.slot)
+            .set_if_some("timestamp", truncated.timestamp)
+            .set("log_index", truncated.log_index)
+            .set("unlogged_invocations", truncated.unlogged_invocations)
+            .set("recovered_events", truncated.recovered_events)
+            .set(
+                "unrecovered_instructions",
+                truncated.unrecovered_instructions.join(","),
+            );
+    }
+
+    for delta in positions.deltas.iter() {
+        let position = &delta.new_value;
+        let key = program_key(&position.program_id, &position.user);
+        let row = match delta.operation {
+            Operation::Create => tables.tables.create_row("UserPosition", &key),
+            _ => tables.tables.update_row("UserPosition", &key),
+        };
+        row.set("program_id", &position.program_id)
+            .set("user", &position.user)
+            .set("total_amount", position.total_amount)
+            .set("lock_expires", position.lock_expires)
+            .set_if_some("first_deposit_slot", position.first_deposit_slot)
+            .set("last_activity_slot", position.last_activity_slot)
+            .set("deposit_count", position.deposit_count);
+    }
+
+    for error in events.errors.iter() {
+        tables
+            .tables
+            .create_row("Error", &quarantine::key(error))
+            .set("description", &error.description)
+            .set("program_id", &error.program_id)
+            .set("kind", error.kind().as_str_name())
+            .set_if_some("event", error.event.as_deref())
+            .set_if_some("discriminator", error.discriminator.as_deref())
+            .set_if_some("message", error.message.as_deref())
+            .set("trailing_bytes", error.trailing_bytes)
+            .set("slot", error.slot)
+            .set("tx_signature", &error.tx_signature)
+            .set_if_some("timestamp", error.timestamp)
+            .set("source", error.source().as_str_name())
+            .set("log_index", error.log_index)
+            .set("instruction_index", error.instruction_index)
+            .set("inner_instruction_index", error.inner_instruction_index)
+            .set("payload", &error.payload);
+        quarantine::create_row(&mut tables.tables, error);
+    }
 }
 
-trait SetIfSome {
-    fn set_if_some<T: ToValue>(&mut self, name: &str, value: Option<T>) -> &mut Self;
+/// The short code of an event, flagged if a different code was registered
+/// with the same display form before.
+fn short_code(
+    event: &Event,
+    short: &[u8],
+    short_code_displays: &impl StoreGet<String>,
+) -> ShortCode {
+    let short = ShortCode::new(short);
+    let first_code = short_code_displays.get_last(store_key(&event.program_id, &short.display));
+    short.with_first_code(first_code.as_deref())
 }
-impl SetIfSome for substreams_entity_change::tables::Row {
-    fn set_if_some<T: ToValue>(&mut self, name: &str, value: Option<T>) -> &mut Self {
-        match value {
-            Some(value) => self.set(name, value),
-            None => self,
+
+/// Key of a `ReferrerShortCode` row within a deployment.
+fn referrer_short_code_key(full: &str, short: &ShortCode) -> String {
+    format!("{full}-{}", short.hex)
+}
+
+/// Points a short code to `full`, keeping its history and the reverse lookup
+/// from full addresses to their codes up to date. `initiator` is set for
+/// registrations made by an admin.
+fn register_short_referrer<'a, T: Tables>(
+    tables: &mut TablesWithIncrementingKey<T>,
+    stored_short_referrers: &mut HashMap<&'a str, Option<&'a str>>,
+    stored_referrer_short_codes: &HashSet<&str>,
+    event: &Event,
+    short: &ShortCode,
+    full: &'a str,
+    initiator: Option<&str>,
+) {
+    let previous_full = stored_short_referrers
+        .get_mut(store_key(&event.program_id, &short.hex).as_str())
+        .and_then(|stored| stored.replace(full));
+
+    // A code pointing somewhere already has a row, from this block or an
+    // earlier one. Otherwise it may have been deleted within the block.
+    let key = program_key(&event.program_id, &short.hex);
+    let row = match previous_full {
+        Some(_) => tables.update_row("ShortReferrer", &key),
+        None => tables.create_or_replace_row("ShortReferrer", &key),
+    };
+    row.set("program_id", &event.program_id).set("full", full);
+    set_short_code(row, short);
+    create_short_referrer_registration_row(tables, event, short, initiator)
+        .set("full", full)
+        .set_if_some("previous_full", previous_full);
+
+    if let Some(previous_full) = previous_full.filter(|previous_full| *previous_full != full) {
+        deactivate_referrer_short_code(tables, event, previous_full, short);
+    }
+
+    // Rows of the reverse lookup are never deleted, only deactivated, so a
+    // pair registered in an earlier block still has one.
+    let key = referrer_short_code_key(full, short);
+    let row_key = program_key(&event.program_id, &key);
+    let row = if stored_referrer_short_codes.contains(store_key(&event.program_id, &key).as_str()) {
+        tables.update_row("ReferrerShortCode", &row_key)
+    } else {
+        tables.create_or_replace_row("ReferrerShortCode", &row_key)
+    };
+    row.set("program_id", &event.program_id)
+        .set("full", full)
+        .set("active", true)
+        .set("registered_slot", event.slot);
+    set_short_code(row, short);
+}
+
+/// Records a change of a short code. `initiator` is set for actions made by
+/// an admin.
+fn create_short_referrer_registration_row<'a, T: Tables>(
+    tables: &'a mut TablesWithIncrementingKey<T>,
+    event: &Event,
+    short: &ShortCode,
+    initiator: Option<&str>,
+) -> &'a mut T::Row {
+    let row = tables
+        .create_row_with_incrementing_key("ShortReferrerRegistration")
+        .set("program_id", &event.program_id)
+        .set("action", event.event.as_ref().map_or("", |e| e.name()));
+    set_short_code(row, short)
+        .set_if_some("initiator", initiator)
+        .set("admin", initiator.is_some())
+        .set("slot", event.slot)
+        .set_if_some("timestamp", event.timestamp)
+        .set("tx_signature", &event.tx_signature)
+}
+
+/// Marks a past code of `full` in the reverse lookup.
+fn deactivate_referrer_short_code<T: Tables>(
+    tables: &mut TablesWithIncrementingKey<T>,
+    event: &Event,
+    full: &str,
+    short: &ShortCode,
+) {
+    tables
+        .update_row(
+            "ReferrerShortCode",
+            &program_key(&event.program_id, &referrer_short_code_key(full, short)),
+        )
+        .set("active", false)
+        .set("removed_slot", event.slot);
+}
+
+/// Sets the columns describing a short code. Rows are keyed by its hex form,
+/// the display form is only meant for humans.
+fn set_short_code<'a, R: Row>(row: &'a mut R, short: &ShortCode) -> &'a mut R {
+    row.set("short", &short.display)
+        .set("short_hex", &short.hex)
+        .set("short_base64", &short.base64)
+        .set("short_status", short.status.as_str())
+}
+
+/// Records a privileged action in the `AdminAction` audit trail. The caller
+/// sets the target and amount, depending on the action.
+fn create_admin_action_row<'a, T: Tables>(
+    tables: &'a mut TablesWithIncrementingKey<T>,
+    event: &Event,
+    initiator: &str,
+) -> &'a mut T::Row {
+    tables
+        .create_row_with_incrementing_key("AdminAction")
+        .set("program_id", &event.program_id)
+        .set("action", event.event.as_ref().map_or("", |e| e.name()))
+        .set("initiator", initiator)
+        .set("slot", event.slot)
+        .set("tx_signature", &event.tx_signature)
+        .set_if_some("timestamp", event.timestamp)
+}
+
+/// Key of a row scoped to a deployment, so several deployments can be indexed
+/// side by side.
+fn program_key(program_id: &str, key: &str) -> String {
+    format!("{program_id}-{key}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{self, GummyEvent, Pubkey};
+    use crate::fixtures::{
+        block, program_data_line, program_data_line_from_bytes, TransactionBuilder, PROGRAM_ID,
+    };
+    use pb::sf::solana::event::v1::{RegisterShortReferrer, SetReferrer};
+    use substreams::pb::substreams::StoreDelta;
+    use substreams_entity_change::pb::entity::entity_change;
+    use substreams_entity_change::pb::entity::value::Typed;
+    use substreams_entity_change::pb::entity::EntityChange;
+
+    fn set_referrer(tx_signature: &str, user: &str, old: &str, new: &str) -> Event {
+        Event {
+            slot: 264062815,
+            tx_signature: tx_signature.to_string(),
+            program_id: PROGRAM_ID.to_string(),
+            event: Some(event::Event::SetReferrer(SetReferrer {
+                user: user.to_string(),
+                old_referrer: old.to_string(),
+                new_referrer: new.to_string(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    fn register_short_referrer(tx_signature: &str, short: &str, full: &str) -> Event {
+        Event {
+            slot: 264062815,
+            tx_signature: tx_signature.to_string(),
+            program_id: PROGRAM_ID.to_string(),
+            event: Some(event::Event::RegisterShortReferrer(RegisterShortReferrer {
+                full: full.to_string(),
+                short: short.as_bytes().to_vec(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// A `StoreSetString` delta, created if `old` is `None`.
+    fn string_delta(key: &str, old: Option<&str>, new: &str) -> StoreDelta {
+        StoreDelta {
+            operation: match old {
+                Some(_) => Operation::Update,
+                None => Operation::Create,
+            } as i32,
+            ordinal: 0,
+            key: key.to_string(),
+            old_value: old.unwrap_or_default().as_bytes().to_vec(),
+            new_value: new.as_bytes().to_vec(),
         }
     }
+
+    /// The store inputs of `build_tables`, but for positions.
+    #[derive(Default)]
+    struct Stores {
+        referrers: Vec<StoreDelta>,
+        short_referrers: Vec<StoreDelta>,
+        referrer_short_codes: Vec<StoreDelta>,
+        short_code_displays: MemoryStore<String>,
+    }
+
+    fn entity_changes_with_stores(events: Vec<Event>, stores: Stores) -> Vec<EntityChange> {
+        let mut tables = TablesWithIncrementingKey::<EntityTables>::new();
+        build_tables(
+            &mut tables,
+            &Events {
+                events,
+                ..Default::default()
+            },
+            &Deltas::new(Vec::new()),
+            &Deltas::new(stores.referrers),
+            &Deltas::new(stores.short_referrers),
+            &Deltas::new(stores.referrer_short_codes),
+            &stores.short_code_displays,
+        );
+        tables.to_entity_changes().entity_changes
+    }
+
+    fn entity_changes(
+        events: Vec<Event>,
+        referrers: Vec<StoreDelta>,
+        short_referrers: Vec<StoreDelta>,
+    ) -> Vec<EntityChange> {
+        entity_changes_with_stores(
+            events,
+            Stores {
+                referrers,
+                short_referrers,
+                ..Default::default()
+            },
+        )
+    }
+
+    /// The changes of `entity`, sorted by key.
+    fn changes_of<'a>(changes: &'a [EntityChange], entity: &str) -> Vec<&'a EntityChange> {
+        let mut changes: Vec<_> = changes.iter().filter(|c| c.entity == entity).collect();
+        changes.sort_by(|a, b| a.id.cmp(&b.id));
+        changes
+    }
+
+    fn field(change: &EntityChange, name: &str) -> Option<Typed> {
+        change
+            .fields
+            .iter()
+            .find(|field| field.name == name)
+            .and_then(|field| field.new_value.as_ref())
+            .and_then(|value| value.typed.clone())
+    }
+
+    fn string(value: &str) -> Option<Typed> {
+        Some(Typed::String(value.to_string()))
+    }
+
+    #[test]
+    fn referrer_row_is_created_once() {
+        let user_key = store_key(PROGRAM_ID, "user");
+
+        // The user's first referrer, changed again later in the block.
+        let changes = entity_changes(
+            vec![
+                set_referrer("tx1", "user", "", "a"),
+                set_referrer("tx2", "user", "a", "b"),
+            ],
+            vec![
+                string_delta(&user_key, None, "a"),
+                string_delta(&user_key, Some("a"), "b"),
+            ],
+            Vec::new(),
+        );
+        let referrers = changes_of(&changes, "Referrer");
+        assert_eq!(referrers.len(), 1);
+        assert_eq!(referrers[0].operation(), entity_change::Operation::Create);
+
+        // A user whose referrer was stored in an earlier block.
+        let changes = entity_changes(
+            vec![set_referrer("tx3", "user", "b", "c")],
+            vec![string_delta(&user_key, Some("b"), "c")],
+            Vec::new(),
+        );
+        let referrers = changes_of(&changes, "Referrer");
+        assert_eq!(referrers.len(), 1);
+        assert_eq!(referrers[0].operation(), entity_change::Operation::Update);
+    }
+
+    #[test]
+    fn referrer_change_compares_old_referrer_with_the_stored_one() {
+        let user_key = store_key(PROGRAM_ID, "user");
+        let changes = entity_changes(
+            vec![
+                // The store saw "a" last, the event claims it was "x".
+                set_referrer("tx1", "user", "x", "b"),
+                set_referrer("tx2", "user", "b", "c"),
+            ],
+            vec![
+                string_delta(&user_key, Some("a"), "b"),
+                string_delta(&user_key, Some("b"), "c"),
+            ],
+            Vec::new(),
+        );
+        let referrer_changes = changes_of(&changes, "ReferrerChange");
+        let compared: Vec<_> = referrer_changes
+            .iter()
+            .map(|change| {
+                (
+                    field(change, "stored_referrer"),
+                    field(change, "old_referrer_mismatch"),
+                )
+            })
+            .collect();
+        assert_eq!(
+            compared,
+            [
+                (string("a"), Some(Typed::Bool(true))),
+                (string("b"), Some(Typed::Bool(false))),
+            ]
+        );
+    }
+
+    #[test]
+    fn first_referrer_of_a_user_has_nothing_to_compare() {
+        let user_key = store_key(PROGRAM_ID, "user");
+        let changes = entity_changes(
+            vec![
+                set_referrer("tx1", "user", "", "a"),
+                set_referrer("tx2", "user", "x", "b"),
+            ],
+            vec![
+                string_delta(&user_key, None, "a"),
+                string_delta(&user_key, Some("a"), "b"),
+            ],
+            Vec::new(),
+        );
+        let referrer_changes = changes_of(&changes, "ReferrerChange");
+        assert_eq!(referrer_changes.len(), 2);
+        assert_eq!(field(referrer_changes[0], "stored_referrer"), None);
+        assert_eq!(field(referrer_changes[0], "old_referrer_mismatch"), None);
+        // Later changes in the block compare against the first one.
+        assert_eq!(field(referrer_changes[1], "stored_referrer"), string("a"));
+        assert_eq!(
+            field(referrer_changes[1], "old_referrer_mismatch"),
+            Some(Typed::Bool(true))
+        );
+    }
+
+    #[test]
+    fn short_referrer_row_is_updated_when_the_code_is_stored() {
+        let code_key = store_key(PROGRAM_ID, &ShortCode::new(b"gummy").hex);
+        let changes = entity_changes(
+            vec![register_short_referrer("tx1", "gummy", "b")],
+            Vec::new(),
+            vec![string_delta(&code_key, Some("a"), "b")],
+        );
+        let short_referrers = changes_of(&changes, "ShortReferrer");
+        assert_eq!(short_referrers.len(), 1);
+        assert_eq!(
+            short_referrers[0].operation(),
+            entity_change::Operation::Update
+        );
+
+        // A code deleted in an earlier block is registered anew.
+        let changes = entity_changes(
+            vec![register_short_referrer("tx1", "gummy", "b")],
+            Vec::new(),
+            vec![string_delta(&code_key, Some(""), "b")],
+        );
+        let short_referrers = changes_of(&changes, "ShortReferrer");
+        assert_eq!(
+            short_referrers[0].operation(),
+            entity_change::Operation::Create
+        );
+    }
+
+    #[test]
+    fn registration_by_the_user_has_no_initiator() {
+        let changes = entity_changes(
+            vec![register_short_referrer("tx1", "gummy", "a")],
+            Vec::new(),
+            Vec::new(),
+        );
+        let registrations = changes_of(&changes, "ShortReferrerRegistration");
+        assert_eq!(field(registrations[0], "initiator"), None);
+        assert_eq!(field(registrations[0], "admin"), Some(Typed::Bool(false)));
+        assert_eq!(field(registrations[0], "full"), string("a"));
+    }
+
+    #[test]
+    fn referrer_short_code_row_is_updated_when_the_pair_is_stored() {
+        let short = ShortCode::new(b"gummy");
+        let code_key = store_key(PROGRAM_ID, &short.hex);
+        let pair_key = |full| store_key(PROGRAM_ID, &referrer_short_code_key(full, &short));
+        // The code moves back to "a", which had it before "b" did.
+        let changes = entity_changes_with_stores(
+            vec![register_short_referrer("tx1", "gummy", "a")],
+            Stores {
+                short_referrers: vec![string_delta(&code_key, Some("b"), "a")],
+                referrer_short_codes: vec![StoreDelta {
+                    operation: Operation::Update as i32,
+                    key: pair_key("a"),
+                    old_value: b"100".to_vec(),
+                    new_value: b"264062815".to_vec(),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            },
+        );
+        let referrer_short_codes = changes_of(&changes, "ReferrerShortCode");
+        let operations: Vec<_> = referrer_short_codes
+            .iter()
+            .map(|change| (change.id.clone(), change.operation()))
+            .collect();
+        assert_eq!(
+            operations,
+            [
+                (
+                    program_key(PROGRAM_ID, &referrer_short_code_key("a", &short)),
+                    entity_change::Operation::Update
+                ),
+                (
+                    program_key(PROGRAM_ID, &referrer_short_code_key("b", &short)),
+                    entity_change::Operation::Update
+                ),
+            ]
+        );
+        assert_eq!(
+            field(referrer_short_codes[0], "active"),
+            Some(Typed::Bool(true))
+        );
+        assert_eq!(
+            field(referrer_short_codes[1], "active"),
+            Some(Typed::Bool(false))
+        );
+
+        // The first registration of the pair.
+        let changes = entity_changes_with_stores(
+            vec![register_short_referrer("tx1", "gummy", "a")],
+            Stores {
+                referrer_short_codes: vec![StoreDelta {
+                    operation: Operation::Create as i32,
+                    key: pair_key("a"),
+                    new_value: b"264062815".to_vec(),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            },
+        );
+        let referrer_short_codes = changes_of(&changes, "ReferrerShortCode");
+        assert_eq!(
+            referrer_short_codes[0].operation(),
+            entity_change::Operation::Create
+        );
+    }
+
+    #[test]
+    fn short_code_reading_like_a_stored_one_is_ambiguous() {
+        let short_code_displays = MemoryStore::default();
+        short_code_displays.set_if_not_exists(
+            0,
+            store_key(PROGRAM_ID, "gummy"),
+            &ShortCode::new(b"gummy").hex,
+        );
+        let changes = entity_changes_with_stores(
+            vec![
+                register_short_referrer("tx1", "GUMMY", "a"),
+                register_short_referrer("tx2", "gummy", "b"),
+            ],
+            Stores {
+                short_code_displays,
+                ..Default::default()
+            },
+        );
+        let statuses: Vec<_> = changes_of(&changes, "ShortReferrer")
+            .iter()
+            .map(|change| (field(change, "short_hex"), field(change, "short_status")))
+            .collect();
+        assert_eq!(
+            statuses,
+            [
+                (string(&ShortCode::new(b"GUMMY").hex), string("ambiguous")),
+                (string(&ShortCode::new(b"gummy").hex), string("valid")),
+            ]
+        );
+    }
+
+    #[test]
+    fn events_round_trip_through_the_pipeline() {
+        let program_id: Pubkey = PROGRAM_ID.parse().unwrap();
+        let deposit = GummyEvent::Deposit(events::Deposit {
+            user: Pubkey([2; 32]),
+            amount: 10,
+            total_amount: 10,
+            lock_expires: 1_800_000_000,
+            referrer: Pubkey([3; 32]),
+        });
+        let withdraw = GummyEvent::Withdraw(events::Withdraw {
+            user: Pubkey([2; 32]),
+            total_amount: 4,
+        });
+        let set_referrer = GummyEvent::SetReferrer(events::SetReferrer {
+            user: Pubkey([2; 32]),
+            old_referrer: Pubkey([3; 32]),
+            new_referrer: Pubkey([4; 32]),
+        });
+        let block = block(
+            264062815,
+            1_717_000_000,
+            vec![
+                TransactionBuilder::new([5; 64])
+                    .instruction(&program_id, &[program_data_line(&deposit)])
+                    .emit_cpi(&program_id, &[withdraw])
+                    .cpi(
+                        &Pubkey([6; 32]),
+                        &program_id,
+                        &[program_data_line(&set_referrer)],
+                    )
+                    .build(),
+                TransactionBuilder::new([7; 64])
+                    .instruction(&program_id, &[program_data_line(&deposit)])
+                    .failed(6001)
+                    .build(),
+            ],
+        );
+
+        let events = gummy_events(&format!("program_ids={PROGRAM_ID}"), &block).unwrap();
+        assert!(events.errors.is_empty());
+        let decoded: Vec<_> = events
+            .events
+            .iter()
+            .map(|event| (event.source(), event.event.as_ref().unwrap().name()))
+            .collect();
+        assert_eq!(
+            decoded,
+            [
+                (Source::Log, "Deposit"),
+                (Source::Log, "SetReferrer"),
+                (Source::InnerInstruction, "Withdraw"),
+            ]
+        );
+        let Some(event::Event::Withdraw(withdraw)) = &events.events[2].event else {
+            unreachable!();
+        };
+        assert_eq!(withdraw.total_amount, 4);
+
+        let events = gummy_events(
+            &format!("program_ids={PROGRAM_ID}&record_failed_transactions=true"),
+            &block,
+        )
+        .unwrap();
+        assert_eq!(events.failed_attempts.len(), 1);
+        assert_eq!(
+            events.failed_attempts[0].error,
+            "InstructionError(0, Custom(6001))"
+        );
+    }
+
+    #[test]
+    fn malformed_input_does_not_panic() {
+        let program_id: Pubkey = PROGRAM_ID.parse().unwrap();
+        // A `short` claiming to hold `u32::MAX` bytes.
+        let mut giant_short = events::DISCRIMINATOR_REGISTER_SHORT_REFERRER.to_vec();
+        giant_short.extend([3; 32]);
+        giant_short.extend(u32::MAX.to_le_bytes());
+        giant_short.extend(b"gummy");
+        let lines = [
+            program_data_line_from_bytes(&giant_short),
+            program_data_line_from_bytes(&events::DISCRIMINATOR_REGISTER_SHORT_REFERRER[..3]),
+        ];
+        let mut unsigned = TransactionBuilder::new([5; 64])
+            .instruction(&program_id, &lines)
+            .build();
+        unsigned.transaction.as_mut().unwrap().signatures.clear();
+        let block = block(
+            264062815,
+            1_717_000_000,
+            vec![
+                unsigned,
+                TransactionBuilder::new([6; 64])
+                    .instruction(&program_id, &lines)
+                    .build(),
+            ],
+        );
+
+        let events = gummy_events(&format!("program_ids={PROGRAM_ID}"), &block).unwrap();
+        assert!(events.events.is_empty());
+        let errors: Vec<_> = events.errors.iter().map(|e| e.log_index).collect();
+        assert_eq!(errors, [1, 2]);
+        entity_changes_without_stores(&events);
+    }
+
+    #[test]
+    fn short_code_deleted_and_registered_again_within_a_block() {
+        let program_id: Pubkey = PROGRAM_ID.parse().unwrap();
+        let register = |full| {
+            GummyEvent::RegisterShortReferrer(events::RegisterShortReferrer {
+                full: Pubkey([full; 32]),
+                short: b"gummy".to_vec(),
+            })
+        };
+        let delete = GummyEvent::AdminDeleteShortReferrer(events::AdminDeleteShortReferrer {
+            short: b"gummy".to_vec(),
+            initiator: Pubkey([9; 32]),
+        });
+        let lines = [
+            register(3),
+            delete.clone(),
+            register(4),
+            delete,
+            register(3),
+        ]
+        .map(|event| program_data_line(&event));
+        let block = block(
+            264062815,
+            1_717_000_000,
+            vec![TransactionBuilder::new([5; 64])
+                .instruction(&program_id, &lines)
+                .build()],
+        );
+
+        let events = gummy_events(&format!("program_ids={PROGRAM_ID}"), &block).unwrap();
+        let changes = entity_changes_without_stores(&events);
+        let short_referrers: Vec<_> = changes
+            .entity_changes
+            .iter()
+            .filter(|change| change.entity == "ShortReferrer")
+            .collect();
+        assert_eq!(short_referrers.len(), 1);
+        // Created within the block, so still sent as a creation.
+        assert_eq!(
+            short_referrers[0].operation(),
+            entity_change::Operation::Create
+        );
+        let full = short_referrers[0]
+            .fields
+            .iter()
+            .find(|field| field.name == "full")
+            .and_then(|field| field.new_value.as_ref())
+            .and_then(|value| value.typed.clone());
+        assert_eq!(full, Some(Typed::String(Pubkey([3; 32]).to_string())));
+    }
+
+    #[test]
+    fn withdrawn_amount_falls_back_on_token_balances() {
+        let program_id: Pubkey = PROGRAM_ID.parse().unwrap();
+        let (user, vault) = (Pubkey([2; 32]), Pubkey([3; 32]));
+        let (user_account, vault_account) = (Pubkey([10; 32]), Pubkey([11; 32]));
+        let (mint, other_mint) = (Pubkey([6; 32]), Pubkey([7; 32]));
+        let withdraw = || {
+            program_data_line(&GummyEvent::Withdraw(events::Withdraw {
+                user,
+                total_amount: 40,
+            }))
+        };
+        let transactions = vec![
+            TransactionBuilder::new([1; 64])
+                .instruction(&program_id, &[withdraw()])
+                .token_balance(&user_account, &user, &mint, 5, 65)
+                .token_balance(&vault_account, &vault, &mint, 100, 40)
+                .build(),
+            // The user also received another mint, which one was withdrawn is
+            // unclear.
+            TransactionBuilder::new([2; 64])
+                .instruction(&program_id, &[withdraw()])
+                .token_balance(&user_account, &user, &mint, 65, 125)
+                .token_balance(&Pubkey([12; 32]), &user, &other_mint, 0, 3)
+                .build(),
+            // Two withdrawals share the balances.
+            TransactionBuilder::new([3; 64])
+                .instruction(&program_id, &[withdraw(), withdraw()])
+                .token_balance(&user_account, &user, &mint, 125, 185)
+                .build(),
+        ];
+        let block = block(264062815, 1_717_000_000, transactions);
+
+        let events = gummy_events(&format!("program_ids={PROGRAM_ID}"), &block).unwrap();
+        let increases: Vec<_> = events
+            .events
+            .iter()
+            .map(|event| event.token_balance_increase)
+            .collect();
+        assert_eq!(increases, [Some(60), None, None, None]);
+
+        // Without stores, the previous staked totals are unknown.
+        let changes = entity_changes_without_stores(&events);
+        let withdrawn: Vec<_> = changes_of(&changes.entity_changes, "WithdrawEvent")
+            .iter()
+            .filter_map(|change| field(change, "withdrawn_amount"))
+            .collect();
+        assert_eq!(withdrawn, [Typed::Bigint("60".to_string())]);
+    }
+
+    #[test]
+    fn idl_events_get_the_columns_of_their_message() {
+        let deposit = events::Deposit {
+            user: Pubkey([2; 32]),
+            amount: 5,
+            total_amount: 7,
+            lock_expires: 1_720_000_000,
+            referrer: Pubkey([3; 32]),
+        };
+        let event = |event| Event {
+            slot: 264062815,
+            tx_signature: "tx1".to_string(),
+            program_id: PROGRAM_ID.to_string(),
+            event: Some(event),
+            ..Default::default()
+        };
+        // An event of the IDL without a message of its own, as a new event
+        // would be decoded.
+        let idl_event = event::Event::IdlEvent(pb::sf::solana::event::v1::IdlEvent {
+            name: "Deposit".to_string(),
+            data: borsh::to_vec(&deposit).unwrap(),
+        });
+        let events = Events {
+            events: vec![event(GummyEvent::Deposit(deposit).into()), event(idl_event)],
+            ..Default::default()
+        };
+
+        let changes = entity_changes_without_stores(&events);
+        let columns = |entity| {
+            let change = changes_of(&changes.entity_changes, entity)[0];
+            ["user", "amount", "total_amount", "lock_expires", "referrer"]
+                .map(|name| field(change, name).unwrap())
+        };
+        assert_eq!(columns("Deposit"), columns("DepositEvent"));
+    }
 }
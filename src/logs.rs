@@ -0,0 +1,294 @@
+//! Walks a transaction's `log_messages` while tracking the program invocation
+//! stack, so every `Program data:` line can be attributed to the program that
+//! emitted it, whatever the CPI depth.
+
+use crate::decoder::LOG_EVENT_PREFIX;
+use crate::events::Pubkey;
+
+/// Last line of the logs when the runtime ran out of log space.
+const LOG_TRUNCATED: &str = "Log truncated";
+
+/// A `Program data:` line together with the program that logged it.
+pub struct ProgramData<'a> {
+    pub program_id: &'a str,
+    /// Index of the line within the transaction's log messages.
+    pub log_index: usize,
+    /// The base64 payload, without the `Program data: ` prefix.
+    pub data: &'a str,
+}
+
+enum LogLine<'a> {
+    Invoke { program_id: &'a str, depth: usize },
+    Success { program_id: &'a str },
+    Failed { program_id: &'a str },
+    Data(&'a str),
+    Other,
+}
+
+fn parse_line(line: &str) -> LogLine<'_> {
+    if let Some(data) = line.strip_prefix(LOG_EVENT_PREFIX) {
+        return LogLine::Data(data);
+    }
+    let Some(rest) = line.strip_prefix("Program ") else {
+        return LogLine::Other;
+    };
+    let Some((program_id, rest)) = rest.split_once(' ') else {
+        return LogLine::Other;
+    };
+    // Programs log free text as `Program log: ...`, `Program data: ...` and
+    // `Program return: ...`, which can look like the runtime's own lines.
+    if program_id.parse::<Pubkey>().is_err() {
+        return LogLine::Other;
+    }
+    if let Some(depth) = rest
+        .strip_prefix("invoke [")
+        .and_then(|rest| rest.strip_suffix(']'))
+    {
+        return match depth.parse() {
+            Ok(depth) => LogLine::Invoke { program_id, depth },
+            Err(_) => LogLine::Other,
+        };
+    }
+    if rest == "success" {
+        return LogLine::Success { program_id };
+    }
+    if rest.starts_with("failed") {
+        return LogLine::Failed { program_id };
+    }
+    // `consumed X of Y compute units` and anything else leave the invocation
+    // stack untouched.
+    LogLine::Other
+}
+
+/// Index of the line marking that the remaining logs were dropped, if any.
+pub fn truncation_index(log_messages: &[String]) -> Option<usize> {
+    log_messages.iter().position(|line| line == LOG_TRUNCATED)
+}
+
+/// Number of invocations of `program_id` whose logs are complete. When the
+/// logs were cut, an invocation still running at that point lost its later
+/// lines, so it and the ones after it are not counted.
+pub fn logged_invocation_count(log_messages: &[String], program_id: &str) -> usize {
+    // Open frames, with the position of the invocation among those of
+    // `program_id`.
+    let mut stack: Vec<(&str, Option<usize>)> = Vec::new();
+    let mut count = 0;
+    for line in log_messages {
+        if line == LOG_TRUNCATED {
+            return match stack.iter().find_map(|(_, position)| *position) {
+                Some(position) => position,
+                None => count,
+            };
+        }
+        match parse_line(line) {
+            LogLine::Invoke {
+                program_id: id,
+                depth,
+            } => {
+                stack.truncate(depth.saturating_sub(1));
+                let position = (id == program_id).then(|| {
+                    count += 1;
+                    count - 1
+                });
+                stack.push((id, position));
+            }
+            LogLine::Success { program_id: id } | LogLine::Failed { program_id: id } => {
+                if let Some(position) = stack.iter().rposition(|(open, _)| *open == id) {
+                    stack.truncate(position);
+                }
+            }
+            LogLine::Data(_) | LogLine::Other => {}
+        }
+    }
+    count
+}
+
+/// Iterates over the `Program data:` lines of a transaction.
+pub struct ProgramDataIter<'a> {
+    log_messages: std::iter::Enumerate<std::slice::Iter<'a, String>>,
+    stack: Vec<&'a str>,
+}
+
+pub fn program_data(log_messages: &[String]) -> ProgramDataIter<'_> {
+    ProgramDataIter {
+        log_messages: log_messages.iter().enumerate(),
+        stack: Vec::new(),
+    }
+}
+
+/// Closes the frame of `program_id`. Frames are normally closed in order; if a
+/// closing line was lost, unwind up to the frame it belongs to.
+fn pop(stack: &mut Vec<&str>, program_id: &str) {
+    if let Some(position) = stack.iter().rposition(|id| *id == program_id) {
+        stack.truncate(position);
+    }
+}
+
+impl<'a> Iterator for ProgramDataIter<'a> {
+    type Item = ProgramData<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for (log_index, line) in self.log_messages.by_ref() {
+            match parse_line(line) {
+                LogLine::Invoke { program_id, depth } => {
+                    // The runtime reports the depth explicitly, trust it over
+                    // our own bookkeeping.
+                    self.stack.truncate(depth.saturating_sub(1));
+                    self.stack.push(program_id);
+                }
+                LogLine::Success { program_id } | LogLine::Failed { program_id } => {
+                    pop(&mut self.stack, program_id)
+                }
+                LogLine::Data(data) => {
+                    if let Some(program_id) = self.stack.last() {
+                        return Some(ProgramData {
+                            program_id,
+                            log_index,
+                            data,
+                        });
+                    }
+                }
+                LogLine::Other => {}
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::PROGRAM_ID;
+
+    const OTHER_PROGRAM_ID: &str = "11111111111111111111111111111111";
+
+    fn logs(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn parses_runtime_lines() {
+        let invoke = format!("Program {PROGRAM_ID} invoke [2]");
+        assert!(matches!(
+            parse_line(&invoke),
+            LogLine::Invoke {
+                program_id: PROGRAM_ID,
+                depth: 2
+            }
+        ));
+        let success = format!("Program {PROGRAM_ID} success");
+        assert!(matches!(
+            parse_line(&success),
+            LogLine::Success {
+                program_id: PROGRAM_ID
+            }
+        ));
+        let failed = format!("Program {PROGRAM_ID} failed: custom program error: 0x1771");
+        assert!(matches!(
+            parse_line(&failed),
+            LogLine::Failed {
+                program_id: PROGRAM_ID
+            }
+        ));
+        assert!(matches!(
+            parse_line("Program data: AQID"),
+            LogLine::Data("AQID")
+        ));
+    }
+
+    #[test]
+    fn ignores_program_output_that_looks_like_runtime_lines() {
+        for line in [
+            "Program log: invoke [1]",
+            "Program log: success",
+            "Program log: failed",
+            "Program return: invoke [1]",
+            "Program data: invoke [1]",
+            "Program 0OIl invoke [1]",
+            "Program 6aw4sBovP6yaG1q4y2GpjaQcLZJbBWMJP4aJFs invoke [1]",
+            &format!("Program {PROGRAM_ID} invoke [x]"),
+            &format!("Program {PROGRAM_ID} consumed 1000 of 200000 compute units"),
+            "Log truncated",
+        ] {
+            assert!(
+                matches!(parse_line(line), LogLine::Other | LogLine::Data(_)),
+                "{line}"
+            );
+        }
+    }
+
+    #[test]
+    fn attributes_data_to_the_innermost_invocation() {
+        let log_messages = logs(&[
+            &format!("Program {PROGRAM_ID} invoke [1]"),
+            "Program log: invoke [1]",
+            "Program data: AQ==",
+            &format!("Program {OTHER_PROGRAM_ID} invoke [2]"),
+            "Program data: Ag==",
+            "Program log: success",
+            &format!("Program {OTHER_PROGRAM_ID} success"),
+            "Program data: Aw==",
+            &format!("Program {PROGRAM_ID} success"),
+            "Program data: BA==",
+        ]);
+        let attributed: Vec<_> = program_data(&log_messages)
+            .map(|data| (data.program_id, data.log_index, data.data))
+            .collect();
+        assert_eq!(
+            attributed,
+            [
+                (PROGRAM_ID, 2, "AQ=="),
+                (OTHER_PROGRAM_ID, 4, "Ag=="),
+                (PROGRAM_ID, 7, "Aw=="),
+            ]
+        );
+        assert_eq!(logged_invocation_count(&log_messages, PROGRAM_ID), 1);
+        assert_eq!(logged_invocation_count(&log_messages, "log:"), 0);
+    }
+
+    #[test]
+    fn unwinds_frames_whose_closing_line_is_missing() {
+        let log_messages = logs(&[
+            &format!("Program {PROGRAM_ID} invoke [1]"),
+            &format!("Program {OTHER_PROGRAM_ID} invoke [2]"),
+            &format!("Program {PROGRAM_ID} success"),
+            "Program data: AQ==",
+            &format!("Program {PROGRAM_ID} invoke [1]"),
+            &format!("Program {OTHER_PROGRAM_ID} invoke [2]"),
+            &format!("Program {PROGRAM_ID} invoke [2]"),
+            "Program data: Ag==",
+        ]);
+        let attributed: Vec<_> = program_data(&log_messages)
+            .map(|data| (data.program_id, data.log_index))
+            .collect();
+        assert_eq!(attributed, [(PROGRAM_ID, 7)]);
+    }
+
+    #[test]
+    fn finds_the_truncation_line() {
+        let log_messages = logs(&[
+            &format!("Program {PROGRAM_ID} invoke [1]"),
+            "Program data: AQ==",
+            "Log truncated",
+        ]);
+        assert_eq!(truncation_index(&log_messages), Some(2));
+        assert_eq!(truncation_index(&log_messages[..2]), None);
+    }
+
+    #[test]
+    fn invocation_open_at_the_cut_is_not_logged() {
+        let log_messages = logs(&[
+            &format!("Program {PROGRAM_ID} invoke [1]"),
+            &format!("Program {PROGRAM_ID} success"),
+            &format!("Program {PROGRAM_ID} invoke [1]"),
+            &format!("Program {PROGRAM_ID} invoke [2]"),
+            &format!("Program {PROGRAM_ID} success"),
+            &format!("Program {OTHER_PROGRAM_ID} invoke [2]"),
+            "Log truncated",
+        ]);
+        // The second invocation is still open, its self-invocation is not.
+        assert_eq!(logged_invocation_count(&log_messages, PROGRAM_ID), 1);
+        assert_eq!(logged_invocation_count(&log_messages, OTHER_PROGRAM_ID), 0);
+        assert_eq!(logged_invocation_count(&log_messages[..5], PROGRAM_ID), 3);
+    }
+}
@@ -0,0 +1,218 @@
+//! A store held in memory, standing in for the ones of the substreams runtime
+//! when the modules run natively.
+
+use crate::pb::sf::solana::event::v1::Position;
+use prost::Message;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use substreams::pb::substreams::store_delta::Operation;
+use substreams::pb::substreams::StoreDelta;
+use substreams::scalar::BigInt;
+use substreams::store::{
+    Delta, Deltas, StoreAdd, StoreDelete, StoreGet, StoreMax, StoreMin, StoreNew, StoreSet,
+    StoreSetIfNotExists,
+};
+
+/// A value of a store, encoded in deltas as the runtime does.
+pub trait StoreValue: Clone {
+    fn to_store_bytes(&self) -> Vec<u8>;
+}
+
+impl StoreValue for i64 {
+    fn to_store_bytes(&self) -> Vec<u8> {
+        self.to_string().into_bytes()
+    }
+}
+
+impl StoreValue for String {
+    fn to_store_bytes(&self) -> Vec<u8> {
+        self.clone().into_bytes()
+    }
+}
+
+impl StoreValue for BigInt {
+    fn to_store_bytes(&self) -> Vec<u8> {
+        self.to_string().into_bytes()
+    }
+}
+
+impl StoreValue for Position {
+    fn to_store_bytes(&self) -> Vec<u8> {
+        self.encode_to_vec()
+    }
+}
+
+/// Keeps the last value of every key, and the deltas of the current block.
+/// Ordinals are only recorded in the deltas: reads see the state after all
+/// the writes made so far.
+pub struct MemoryStore<V> {
+    values: RefCell<BTreeMap<String, V>>,
+    deltas: RefCell<Vec<StoreDelta>>,
+}
+
+impl<V> Default for MemoryStore<V> {
+    fn default() -> Self {
+        MemoryStore {
+            values: RefCell::new(BTreeMap::new()),
+            deltas: RefCell::new(Vec::new()),
+        }
+    }
+}
+
+impl<V: StoreValue> MemoryStore<V> {
+    /// The changes made since the last `start_block`, in order.
+    pub fn deltas<T: Delta + From<StoreDelta>>(&self) -> Deltas<T> {
+        Deltas::new(self.deltas.borrow().clone())
+    }
+
+    /// Forgets the deltas of the previous block, keeping the values.
+    pub fn start_block(&self) {
+        self.deltas.borrow_mut().clear();
+    }
+
+    fn write(&self, ord: u64, key: &str, value: V) {
+        let old_value = self
+            .values
+            .borrow_mut()
+            .insert(key.to_string(), value.clone());
+        let operation = match old_value {
+            Some(_) => Operation::Update,
+            None => Operation::Create,
+        };
+        self.deltas.borrow_mut().push(StoreDelta {
+            operation: operation as i32,
+            ordinal: ord,
+            key: key.to_string(),
+            old_value: old_value.map(|v| v.to_store_bytes()).unwrap_or_default(),
+            new_value: value.to_store_bytes(),
+        });
+    }
+}
+
+impl<V> StoreNew for MemoryStore<V> {
+    fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<V: StoreValue> StoreDelete for MemoryStore<V> {
+    fn delete_prefix(&self, ord: i64, prefix: &String) {
+        let mut values = self.values.borrow_mut();
+        let keys: Vec<String> = values
+            .keys()
+            .filter(|key| key.starts_with(prefix.as_str()))
+            .cloned()
+            .collect();
+        for key in keys {
+            let old_value = values.remove(&key).expect("listed above");
+            self.deltas.borrow_mut().push(StoreDelta {
+                operation: Operation::Delete as i32,
+                ordinal: ord as u64,
+                key,
+                old_value: old_value.to_store_bytes(),
+                new_value: Vec::new(),
+            });
+        }
+    }
+}
+
+impl<V: StoreValue> StoreSet<V> for MemoryStore<V> {
+    fn set<K: AsRef<str>>(&self, ord: u64, key: K, value: &V) {
+        self.write(ord, key.as_ref(), value.clone());
+    }
+
+    fn set_many<K: AsRef<str>>(&self, ord: u64, keys: &Vec<K>, value: &V) {
+        for key in keys {
+            self.set(ord, key, value);
+        }
+    }
+}
+
+impl<V: StoreValue> StoreSetIfNotExists<V> for MemoryStore<V> {
+    fn set_if_not_exists<K: AsRef<str>>(&self, ord: u64, key: K, value: &V) {
+        if !self.has_last(&key) {
+            self.write(ord, key.as_ref(), value.clone());
+        }
+    }
+
+    fn set_if_not_exists_many<K: AsRef<str>>(&self, ord: u64, keys: &Vec<K>, value: &V) {
+        for key in keys {
+            self.set_if_not_exists(ord, key, value);
+        }
+    }
+}
+
+impl StoreAdd<i64> for MemoryStore<i64> {
+    fn add<K: AsRef<str>>(&self, ord: u64, key: K, value: i64) {
+        let sum = self.get_last(&key).unwrap_or_default() + value;
+        self.write(ord, key.as_ref(), sum);
+    }
+
+    fn add_many<K: AsRef<str>>(&self, ord: u64, keys: &Vec<K>, value: i64) {
+        for key in keys {
+            self.add(ord, key, value);
+        }
+    }
+}
+
+impl<V: AsRef<BigInt>> StoreAdd<V> for MemoryStore<BigInt> {
+    fn add<K: AsRef<str>>(&self, ord: u64, key: K, value: V) {
+        let sum = self.get_last(&key).unwrap_or_default() + value.as_ref().clone();
+        self.write(ord, key.as_ref(), sum);
+    }
+
+    fn add_many<K: AsRef<str>>(&self, ord: u64, keys: &Vec<K>, value: V) {
+        for key in keys {
+            self.add(ord, key, value.as_ref());
+        }
+    }
+}
+
+/// Like the runtime, only writes, and records a delta, when the value wins.
+impl<V: AsRef<BigInt>> StoreMax<V> for MemoryStore<BigInt> {
+    fn max<K: AsRef<str>>(&self, ord: u64, key: K, value: V) {
+        let value = value.as_ref();
+        if self.get_last(&key).is_none_or(|current| *value > current) {
+            self.write(ord, key.as_ref(), value.clone());
+        }
+    }
+}
+
+impl<V: AsRef<BigInt>> StoreMin<V> for MemoryStore<BigInt> {
+    fn min<K: AsRef<str>>(&self, ord: u64, key: K, value: V) {
+        let value = value.as_ref();
+        if self.get_last(&key).is_none_or(|current| *value < current) {
+            self.write(ord, key.as_ref(), value.clone());
+        }
+    }
+}
+
+impl<V: Clone> StoreGet<V> for MemoryStore<V> {
+    fn new(_idx: u32) -> Self {
+        Self::default()
+    }
+
+    fn get_at<K: AsRef<str>>(&self, _ord: u64, key: K) -> Option<V> {
+        self.get_last(key)
+    }
+
+    fn get_last<K: AsRef<str>>(&self, key: K) -> Option<V> {
+        self.values.borrow().get(key.as_ref()).cloned()
+    }
+
+    fn get_first<K: AsRef<str>>(&self, key: K) -> Option<V> {
+        self.get_last(key)
+    }
+
+    fn has_at<K: AsRef<str>>(&self, _ord: u64, key: K) -> bool {
+        self.has_last(key)
+    }
+
+    fn has_last<K: AsRef<str>>(&self, key: K) -> bool {
+        self.values.borrow().contains_key(key.as_ref())
+    }
+
+    fn has_first<K: AsRef<str>>(&self, key: K) -> bool {
+        self.has_last(key)
+    }
+}
@@ -0,0 +1,113 @@
+//! Runs the modules of `substreams.yaml` up to `map_events` outside of a
+//! substreams runtime, with the stores held in memory, for the golden tests
+//! and for tools processing blocks fetched by other means.
+
+use crate::memory_store::MemoryStore;
+use crate::pb::sf::solana::event::v1::{Events, Position};
+use substreams::pb::substreams::Clock;
+use substreams::scalar::BigInt;
+use substreams_entity_change::pb::entity::EntityChanges;
+use substreams_solana::pb::sf::solana::r#type::v1::Block;
+
+/// The modules with their stores, which keep their values from one block to
+/// the next as in a runtime started at the first block processed.
+#[derive(Default)]
+pub struct NativeModules {
+    params: String,
+    deposit_counts: MemoryStore<i64>,
+    first_deposit_slots: MemoryStore<i64>,
+    lock_expirations: MemoryStore<i64>,
+    positions: MemoryStore<Position>,
+    referrers: MemoryStore<String>,
+    short_referrers: MemoryStore<String>,
+    referrer_short_codes: MemoryStore<i64>,
+    short_code_displays: MemoryStore<String>,
+    tvl: MemoryStore<BigInt>,
+    daily_tvl_open: MemoryStore<BigInt>,
+    daily_tvl_high: MemoryStore<BigInt>,
+    daily_tvl_low: MemoryStore<BigInt>,
+}
+
+impl NativeModules {
+    /// `params` are those of `map_gummy_events`.
+    pub fn new(params: &str) -> Self {
+        NativeModules {
+            params: params.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// The outputs of `map_gummy_events` and `map_events` for `block`, after
+    /// running every store on it.
+    pub fn process(
+        &mut self,
+        block: &Block,
+    ) -> Result<(Events, EntityChanges), substreams::errors::Error> {
+        let clock = clock(block);
+        let events = crate::gummy_events(&self.params, block)?;
+
+        self.deposit_counts.start_block();
+        self.first_deposit_slots.start_block();
+        self.lock_expirations.start_block();
+        self.positions.start_block();
+        self.referrers.start_block();
+        self.short_referrers.start_block();
+        self.referrer_short_codes.start_block();
+        self.short_code_displays.start_block();
+        self.tvl.start_block();
+        self.daily_tvl_open.start_block();
+        self.daily_tvl_high.start_block();
+        self.daily_tvl_low.start_block();
+
+        crate::add_deposit_counts(&events, &self.deposit_counts);
+        crate::set_first_deposit_slots(&events, &self.first_deposit_slots);
+        crate::set_lock_expirations(&events, &self.lock_expirations);
+        crate::set_positions(
+            &events,
+            &self.deposit_counts,
+            &self.first_deposit_slots,
+            &self.lock_expirations,
+            &self.positions,
+        );
+        crate::set_referrers(&events, &self.referrers);
+        crate::set_short_referrers(&events, &self.short_referrers);
+        crate::set_referrer_short_codes(&events, &self.referrer_short_codes);
+        crate::set_short_code_displays(&events, &self.short_code_displays);
+        let positions = self.positions.deltas();
+        crate::add_tvl(&positions, &self.tvl);
+        let tvl = self.tvl.deltas();
+        crate::set_daily_tvl_open(&clock, &tvl, &self.daily_tvl_open);
+        crate::max_daily_tvl(&clock, &events, &positions, &tvl, &self.daily_tvl_high);
+        crate::min_daily_tvl(&clock, &events, &positions, &tvl, &self.daily_tvl_low);
+
+        let changes = crate::entity_changes(
+            &clock,
+            &events,
+            &positions,
+            &self.referrers.deltas(),
+            &self.short_referrers.deltas(),
+            &self.referrer_short_codes.deltas(),
+            &self.short_code_displays,
+            &tvl,
+            &self.daily_tvl_open.deltas(),
+            &self.daily_tvl_high,
+            &self.daily_tvl_low,
+        );
+        Ok((events, changes))
+    }
+}
+
+/// The clock the runtime gives the modules for a Solana block.
+fn clock(block: &Block) -> Clock {
+    Clock {
+        id: block.blockhash.clone(),
+        number: block.slot,
+        timestamp: block
+            .block_time
+            .as_ref()
+            .map(|time| prost_types::Timestamp {
+                seconds: time.timestamp,
+                nanos: 0,
+            }),
+    }
+}
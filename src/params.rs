@@ -0,0 +1,94 @@
+//! Module parameters, passed through `params` in `substreams.yaml` as
+//! `key=value` pairs separated by `&`.
+
+use anyhow::{anyhow, bail, Result};
+
+/// The mainnet deployment, indexed when no `program_ids` are given.
+pub const DEFAULT_PROGRAM_ID: &str = "6aw4sBovP6yaG1q4y2GpjaQcLZJbBWMJP4aJFsLKxgb3";
+
+#[derive(Debug)]
+pub struct Params {
+    /// Deployments of the staking program to index, as a comma separated list
+    /// of base58 addresses.
+    pub program_ids: Vec<ProgramId>,
+    /// Emit events from failed transactions as `FailedAttempt`s instead of
+    /// dropping them.
+    pub record_failed_transactions: bool,
+    /// Accept event payloads longer than their layout, recording how many
+    /// bytes were ignored, instead of failing on them. On by default, so that
+    /// an upgrade appending a field does not turn every event into an error
+    /// before its layout is added.
+    pub lenient_decoding: bool,
+}
+
+#[derive(Debug)]
+pub struct ProgramId {
+    pub address: String,
+    pub bytes: Vec<u8>,
+}
+
+impl ProgramId {
+    pub fn parse(address: &str) -> Result<Self> {
+        let address = address.trim();
+        let bytes = bs58::decode(address)
+            .into_vec()
+            .map_err(|e| anyhow!("invalid program id '{address}': {e}"))?;
+        if bytes.len() != 32 {
+            bail!(
+                "invalid program id '{address}': expected 32 bytes, got {}",
+                bytes.len()
+            );
+        }
+        Ok(ProgramId {
+            address: address.to_string(),
+            bytes,
+        })
+    }
+}
+
+impl Params {
+    pub fn parse(params: &str) -> Result<Self> {
+        let mut parsed = Params {
+            program_ids: vec![ProgramId::parse(DEFAULT_PROGRAM_ID)?],
+            record_failed_transactions: false,
+            lenient_decoding: true,
+        };
+
+        for pair in params.split('&').filter(|pair| !pair.trim().is_empty()) {
+            let (key, value) = pair
+                .split_once('=')
+                .ok_or_else(|| anyhow!("invalid param '{pair}', expected 'key=value'"))?;
+            match key.trim() {
+                "program_ids" => {
+                    parsed.program_ids = value
+                        .split(',')
+                        .map(ProgramId::parse)
+                        .collect::<Result<_>>()?;
+                    for (i, program_id) in parsed.program_ids.iter().enumerate() {
+                        if parsed.program_ids[..i]
+                            .iter()
+                            .any(|other| other.bytes == program_id.bytes)
+                        {
+                            bail!("duplicate program id '{}'", program_id.address);
+                        }
+                    }
+                }
+                "record_failed_transactions" => {
+                    parsed.record_failed_transactions = parse_bool(key, value)?
+                }
+                "lenient_decoding" => parsed.lenient_decoding = parse_bool(key, value)?,
+                _ => bail!("unknown param '{key}'"),
+            }
+        }
+
+        Ok(parsed)
+    }
+}
+
+fn parse_bool(key: &str, value: &str) -> Result<bool> {
+    match value.trim() {
+        "true" => Ok(true),
+        "false" => Ok(false),
+        _ => bail!("invalid value '{value}' for param '{key}', expected 'true' or 'false'"),
+    }
+}
@@ -4,11 +4,334 @@
 pub struct Events {
     #[prost(message, repeated, tag="1")]
     pub events: ::prost::alloc::vec::Vec<Event>,
+    #[prost(message, repeated, tag="2")]
+    pub errors: ::prost::alloc::vec::Vec<DecodeError>,
+    /// Only filled when the `record_failed_transactions` param is set.
+    #[prost(message, repeated, tag="3")]
+    pub failed_attempts: ::prost::alloc::vec::Vec<FailedAttempt>,
+    #[prost(message, repeated, tag="4")]
+    pub truncated_transactions: ::prost::alloc::vec::Vec<TruncatedTransaction>,
 }
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Event {
+    #[prost(uint64, tag="1")]
+    pub slot: u64,
+    #[prost(string, tag="2")]
+    pub tx_signature: ::prost::alloc::string::String,
+    /// Index of the transaction within the block, as seen by this module.
+    #[prost(uint32, tag="3")]
+    pub tx_index: u32,
+    /// Index of the `Program data:` line within the transaction's log messages.
+    /// Only meaningful for events with the `LOG` source.
+    #[prost(uint32, tag="4")]
+    pub log_index: u32,
+    #[prost(int64, optional, tag="5")]
+    pub timestamp: ::core::option::Option<i64>,
+    #[prost(enumeration="Source", tag="6")]
+    pub source: i32,
+    /// Position of the self-CPI carrying the event, for the `INNER_INSTRUCTION`
+    /// source.
+    #[prost(uint32, tag="7")]
+    pub instruction_index: u32,
+    #[prost(uint32, tag="8")]
+    pub inner_instruction_index: u32,
+    /// The deployment of the staking program that emitted the event.
+    #[prost(string, tag="9")]
+    pub program_id: ::prost::alloc::string::String,
+    /// Version of the layout the event was decoded with, 0 for events only
+    /// known from the IDL.
+    #[prost(uint32, tag="17")]
+    pub layout_version: u32,
+    /// Bytes past the end of the layout, ignored in lenient decoding mode.
+    #[prost(uint32, tag="18")]
+    pub ignored_bytes: u32,
+    /// For withdrawals, what the token accounts of the user gained in the
+    /// transaction according to its token balances. Unset unless they gained a
+    /// single mint and the transaction withdraws once for the user.
+    #[prost(uint64, optional, tag="20")]
+    pub token_balance_increase: ::core::option::Option<u64>,
+    #[prost(oneof="event::Event", tags="10, 11, 12, 13, 14, 15, 16, 19")]
+    pub event: ::core::option::Option<event::Event>,
+}
+/// Nested message and enum types in `Event`.
+pub mod event {
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, ::prost::Oneof)]
+    pub enum Event {
+        #[prost(message, tag="10")]
+        Deposit(super::Deposit),
+        #[prost(message, tag="11")]
+        Withdraw(super::Withdraw),
+        #[prost(message, tag="12")]
+        SetReferrer(super::SetReferrer),
+        #[prost(message, tag="13")]
+        RegisterShortReferrer(super::RegisterShortReferrer),
+        #[prost(message, tag="14")]
+        AdminRegisterShortReferrer(super::AdminRegisterShortReferrer),
+        #[prost(message, tag="15")]
+        AdminDeleteShortReferrer(super::AdminDeleteShortReferrer),
+        #[prost(message, tag="16")]
+        AdminEmergencyWithdraw(super::AdminEmergencyWithdraw),
+        #[prost(message, tag="19")]
+        IdlEvent(super::IdlEvent),
+    }
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct DecodeError {
+    #[prost(uint64, tag="1")]
+    pub slot: u64,
+    #[prost(string, tag="2")]
+    pub tx_signature: ::prost::alloc::string::String,
+    #[prost(uint32, tag="3")]
+    pub tx_index: u32,
+    #[prost(uint32, tag="4")]
+    pub log_index: u32,
+    /// Human readable summary of the other fields.
+    #[prost(string, tag="5")]
+    pub description: ::prost::alloc::string::String,
+    #[prost(enumeration="Source", tag="6")]
+    pub source: i32,
+    #[prost(uint32, tag="7")]
+    pub instruction_index: u32,
+    #[prost(uint32, tag="8")]
+    pub inner_instruction_index: u32,
+    #[prost(string, tag="9")]
+    pub program_id: ::prost::alloc::string::String,
+    #[prost(enumeration="DecodeErrorKind", tag="10")]
+    pub kind: i32,
+    /// The event the discriminator matched, for `BORSH_DECODE` and
+    /// `TRAILING_BYTES`.
+    #[prost(string, optional, tag="11")]
+    pub event: ::core::option::Option<::prost::alloc::string::String>,
+    /// Hex encoded, unset when the payload is too short to hold one.
+    #[prost(string, optional, tag="12")]
+    pub discriminator: ::core::option::Option<::prost::alloc::string::String>,
+    /// The base64 payload of the log line, or the base64 encoded instruction
+    /// data without the `emit_cpi!` tag.
+    #[prost(string, tag="13")]
+    pub payload: ::prost::alloc::string::String,
+    /// The borsh error, for `BORSH_DECODE`.
+    #[prost(string, optional, tag="14")]
+    pub message: ::core::option::Option<::prost::alloc::string::String>,
+    /// Number of bytes left over, for `TRAILING_BYTES`.
+    #[prost(uint32, tag="15")]
+    pub trailing_bytes: u32,
+    #[prost(int64, optional, tag="16")]
+    pub timestamp: ::core::option::Option<i64>,
+    /// The transaction error, when the payload was found in a failed
+    /// transaction recorded through `record_failed_transactions`.
+    #[prost(string, optional, tag="17")]
+    pub tx_error: ::core::option::Option<::prost::alloc::string::String>,
+}
+/// An event logged by a transaction that ultimately failed, so none of its
+/// effects were applied.
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct FailedAttempt {
+    #[prost(message, optional, tag="1")]
+    pub event: ::core::option::Option<Event>,
+    /// The transaction error, e.g. `InstructionError(0, Custom(6001))`.
+    #[prost(string, tag="2")]
+    pub error: ::prost::alloc::string::String,
+}
+/// A transaction invoking the program whose logs were cut short by the runtime,
+/// so `Program data:` lines past that point are lost.
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct TruncatedTransaction {
+    #[prost(uint64, tag="1")]
+    pub slot: u64,
+    #[prost(string, tag="2")]
+    pub tx_signature: ::prost::alloc::string::String,
+    #[prost(uint32, tag="3")]
+    pub tx_index: u32,
+    #[prost(int64, optional, tag="4")]
+    pub timestamp: ::core::option::Option<i64>,
+    /// Index of the `Log truncated` line.
+    #[prost(uint32, tag="5")]
+    pub log_index: u32,
+    /// Invocations of the program that do not appear in the logs, or only in
+    /// part as they were running when the logs were cut.
+    #[prost(uint32, tag="6")]
+    pub unlogged_invocations: u32,
+    /// Events recovered from `emit_cpi!` inner instructions among them.
+    #[prost(uint32, tag="7")]
+    pub recovered_events: u32,
+    /// Hex encoded instruction discriminators of the other unlogged invocations,
+    /// whose events could not be recovered.
+    #[prost(string, repeated, tag="8")]
+    pub unrecovered_instructions: ::prost::alloc::vec::Vec<
+        ::prost::alloc::string::String,
+    >,
+    #[prost(string, tag="9")]
+    pub program_id: ::prost::alloc::string::String,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Deposit {
+    #[prost(string, tag="1")]
+    pub user: ::prost::alloc::string::String,
+    #[prost(uint64, tag="2")]
+    pub amount: u64,
+    #[prost(uint64, tag="3")]
+    pub total_amount: u64,
+    #[prost(uint32, tag="4")]
+    pub lock_expires: u32,
+    #[prost(string, tag="5")]
+    pub referrer: ::prost::alloc::string::String,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Withdraw {
+    #[prost(string, tag="1")]
+    pub user: ::prost::alloc::string::String,
+    #[prost(uint64, tag="2")]
+    pub total_amount: u64,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SetReferrer {
+    #[prost(string, tag="1")]
+    pub user: ::prost::alloc::string::String,
+    #[prost(string, tag="2")]
+    pub old_referrer: ::prost::alloc::string::String,
+    #[prost(string, tag="3")]
+    pub new_referrer: ::prost::alloc::string::String,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct RegisterShortReferrer {
+    #[prost(string, tag="1")]
+    pub full: ::prost::alloc::string::String,
+    #[prost(bytes = "vec", tag="2")]
+    pub short: ::prost::alloc::vec::Vec<u8>,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct AdminRegisterShortReferrer {
+    #[prost(string, tag="1")]
+    pub full: ::prost::alloc::string::String,
+    #[prost(bytes = "vec", tag="2")]
+    pub short: ::prost::alloc::vec::Vec<u8>,
+    #[prost(string, tag="3")]
+    pub initiator: ::prost::alloc::string::String,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct AdminDeleteShortReferrer {
+    #[prost(bytes = "vec", tag="1")]
+    pub short: ::prost::alloc::vec::Vec<u8>,
+    #[prost(string, tag="2")]
+    pub initiator: ::prost::alloc::string::String,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct AdminEmergencyWithdraw {
     #[prost(string, tag="1")]
-    pub text: ::prost::alloc::string::String,
+    pub user: ::prost::alloc::string::String,
+    #[prost(uint64, tag="2")]
+    pub total_amount: u64,
+    #[prost(string, tag="3")]
+    pub initiator: ::prost::alloc::string::String,
+}
+/// An event of the IDL without a message of its own, see `src/events.rs`.
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct IdlEvent {
+    #[prost(string, tag="1")]
+    pub name: ::prost::alloc::string::String,
+    /// The borsh serialized event, without its discriminator.
+    #[prost(bytes = "vec", tag="2")]
+    pub data: ::prost::alloc::vec::Vec<u8>,
+}
+/// Running state of a staker, as kept by `store_positions`.
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Position {
+    #[prost(string, tag="1")]
+    pub program_id: ::prost::alloc::string::String,
+    #[prost(string, tag="2")]
+    pub user: ::prost::alloc::string::String,
+    #[prost(uint64, tag="3")]
+    pub total_amount: u64,
+    /// Lock expiry set by the latest deposit, 0 if none was seen.
+    #[prost(uint32, tag="4")]
+    pub lock_expires: u32,
+    /// Unset when the user has only withdrawn since the initial block.
+    #[prost(uint64, optional, tag="5")]
+    pub first_deposit_slot: ::core::option::Option<u64>,
+    #[prost(uint64, tag="6")]
+    pub last_activity_slot: u64,
+    #[prost(uint64, tag="7")]
+    pub deposit_count: u64,
+}
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum Source {
+    /// A `Program data:` log line.
+    Log = 0,
+    /// An Anchor `emit_cpi!` self-invocation.
+    InnerInstruction = 1,
+}
+impl Source {
+    /// String value of the enum field names used in the ProtoBuf definition.
+    ///
+    /// The values are not transformed in any way and thus are considered stable
+    /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+    pub fn as_str_name(&self) -> &'static str {
+        match self {
+            Source::Log => "LOG",
+            Source::InnerInstruction => "INNER_INSTRUCTION",
+        }
+    }
+    /// Creates an enum from field names used in the ProtoBuf definition.
+    pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+        match value {
+            "LOG" => Some(Self::Log),
+            "INNER_INSTRUCTION" => Some(Self::InnerInstruction),
+            _ => None,
+        }
+    }
+}
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum DecodeErrorKind {
+    Unspecified = 0,
+    Base64Decode = 1,
+    /// Shorter than a discriminator.
+    TooShort = 2,
+    UnknownDiscriminator = 3,
+    BorshDecode = 4,
+    TrailingBytes = 5,
+}
+impl DecodeErrorKind {
+    /// String value of the enum field names used in the ProtoBuf definition.
+    ///
+    /// The values are not transformed in any way and thus are considered stable
+    /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+    pub fn as_str_name(&self) -> &'static str {
+        match self {
+            DecodeErrorKind::Unspecified => "DECODE_ERROR_KIND_UNSPECIFIED",
+            DecodeErrorKind::Base64Decode => "BASE64_DECODE",
+            DecodeErrorKind::TooShort => "TOO_SHORT",
+            DecodeErrorKind::UnknownDiscriminator => "UNKNOWN_DISCRIMINATOR",
+            DecodeErrorKind::BorshDecode => "BORSH_DECODE",
+            DecodeErrorKind::TrailingBytes => "TRAILING_BYTES",
+        }
+    }
+    /// Creates an enum from field names used in the ProtoBuf definition.
+    pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+        match value {
+            "DECODE_ERROR_KIND_UNSPECIFIED" => Some(Self::Unspecified),
+            "BASE64_DECODE" => Some(Self::Base64Decode),
+            "TOO_SHORT" => Some(Self::TooShort),
+            "UNKNOWN_DISCRIMINATOR" => Some(Self::UnknownDiscriminator),
+            "BORSH_DECODE" => Some(Self::BorshDecode),
+            "TRAILING_BYTES" => Some(Self::TrailingBytes),
+            _ => None,
+        }
+    }
 }
 // @@protoc_insertion_point(module)
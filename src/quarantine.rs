@@ -0,0 +1,40 @@
+//! Payloads that could not be decoded are kept in the `Error` table, and the
+//! `Quarantine` table adds the context needed to decode them again under the
+//! same key, so that once the decoders learn a new event or layout, past
+//! blocks can be fixed with the `replay` tool instead of a resync.
+
+use crate::pb::sf::solana::event::v1::{DecodeError, Source};
+use crate::tables::{Row, Tables};
+
+pub const TABLE: &str = "Quarantine";
+
+/// Key of the `Error` and quarantine rows of an error. It only depends on
+/// where the payload was found, so a replay can clear the rows it recovered.
+pub fn key(error: &DecodeError) -> String {
+    format!(
+        "{}-{}-{}",
+        error.tx_signature,
+        error.program_id,
+        position(error)
+    )
+}
+
+/// Where the payload was found within its transaction.
+pub fn position(error: &DecodeError) -> String {
+    match error.source() {
+        Source::Log => format!("log-{}", error.log_index),
+        Source::InnerInstruction => format!(
+            "ix-{}.{}",
+            error.instruction_index, error.inner_instruction_index
+        ),
+    }
+}
+
+/// Writes the columns a replay needs besides those of the `Error` row.
+pub fn create_row<T: Tables>(tables: &mut T, error: &DecodeError) {
+    tables
+        .create_row(TABLE, &key(error))
+        .set("tx_index", error.tx_index)
+        .set("tx_failed", error.tx_error.is_some())
+        .set_if_some("tx_error", error.tx_error.as_deref());
+}
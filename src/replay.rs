@@ -0,0 +1,498 @@
+//! Runs rows exported from the `Quarantine` table, joined with their `Error`
+//! rows, through the current decoders, and builds the SQL statements writing
+//! what indexing them would have produced. Used by the `replay` binary.
+//!
+//! Only history tables, whose rows are added and never changed, are replayed.
+//! Tables holding current state, such as `Referrer` or `UserPosition`, depend
+//! on every event in order and on the stores, as do columns computed from
+//! stored values such as `withdrawn_amount`. Recovered events that change them
+//! are reported with the slot to resync those tables from instead, and those
+//! tables stay wrong until that resync.
+//!
+//! Only the SQL sinks are fixed. Consumers of the `EntityChanges` of
+//! `map_events`, such as a subgraph, need a resync from the first slot of the
+//! replayed rows.
+
+use crate::decoder::{self, DecodeOptions, Decoded};
+use crate::memory_store::MemoryStore;
+pub use crate::schema::Dialect;
+
+use crate::pb::sf::solana::event::v1::{DecodeError, Event, Events, FailedAttempt, Source};
+use crate::tables::DatabaseTables;
+use crate::tables_with_incrementing_key::TablesWithIncrementingKey;
+use crate::{build_tables, quarantine};
+use anyhow::{anyhow, bail, Context, Result};
+use base64::prelude::*;
+use std::collections::BTreeSet;
+use substreams::store::Deltas;
+use substreams_database_change::pb::database::table_change::{Operation, PrimaryKey};
+use substreams_database_change::pb::database::{DatabaseChanges, TableChange};
+
+/// Columns expected in each row, tab separated as in the text format of
+/// Postgres `COPY`, with `\N` for null. `tx_index` and `tx_error` come from
+/// the `Quarantine` row, the others from the `Error` row of the same id.
+pub const COLUMNS: &[&str] = &[
+    "program_id",
+    "slot",
+    "tx_signature",
+    "tx_index",
+    "timestamp",
+    "source",
+    "log_index",
+    "instruction_index",
+    "inner_instruction_index",
+    "payload",
+    "tx_error",
+];
+
+/// Tables `build_tables` writes that hold current state.
+const STATE_TABLES: &[&str] = &["Referrer", "ShortReferrer", "ReferrerShortCode"];
+
+/// Tables built from the positions, which a replay has no store to update.
+const POSITION_TABLES: &[&str] = &["UserPosition", "TotalValueLocked", "DailyTotalValueLocked"];
+
+pub struct Replay {
+    pub database_changes: DatabaseChanges,
+    /// Number of rows that now decode, and whose `Error` and quarantine rows
+    /// are deleted.
+    pub recovered: usize,
+    /// Number of recovered rows from failed transactions, written as
+    /// `FailedAttempt`s.
+    pub failed_attempts: usize,
+    /// Quarantine keys of the rows that still fail, with the reason.
+    pub failed: Vec<(String, String)>,
+    /// State tables that recovered events would have changed, and which are
+    /// stale until resynced from `resync_from`.
+    pub stale_tables: BTreeSet<&'static str>,
+    /// Slot of the first recovered event changing a state table.
+    pub resync_from: Option<u64>,
+}
+
+/// `lenient` accepts payloads longer than their layout, see
+/// `DecodeOptions::lenient`.
+pub fn replay(input: &str, lenient: bool) -> Result<Replay> {
+    let mut rows = Vec::new();
+    for (line_index, line) in input.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        rows.push(parse_row(line).with_context(|| format!("line {}", line_index + 1))?);
+    }
+    // Keep the order the events had on chain, whatever the export order.
+    rows.sort_by_key(|row| {
+        (
+            row.slot,
+            row.tx_index,
+            row.source,
+            row.log_index,
+            row.instruction_index,
+            row.inner_instruction_index,
+        )
+    });
+
+    let mut tables = TablesWithIncrementingKey::<DatabaseTables>::new();
+    let mut replay = Replay {
+        database_changes: DatabaseChanges::default(),
+        recovered: 0,
+        failed_attempts: 0,
+        failed: Vec::new(),
+        stale_tables: BTreeSet::new(),
+        resync_from: None,
+    };
+    for row in rows {
+        let decoded = match decode(&row, lenient) {
+            Ok(decoded) => decoded,
+            Err(error) => {
+                replay
+                    .failed
+                    .push((quarantine::key(&row), error.to_string()));
+                continue;
+            }
+        };
+        replay.recovered += 1;
+        let event = Event {
+            slot: row.slot,
+            tx_signature: row.tx_signature.clone(),
+            tx_index: row.tx_index,
+            log_index: row.log_index,
+            timestamp: row.timestamp,
+            source: row.source,
+            instruction_index: row.instruction_index,
+            inner_instruction_index: row.inner_instruction_index,
+            program_id: row.program_id.clone(),
+            layout_version: decoded.layout_version,
+            ignored_bytes: decoded.ignored_bytes as u32,
+            // The token balances are not quarantined.
+            token_balance_increase: None,
+            event: Some(decoded.event.into()),
+        };
+        let mut stale_tables = Vec::new();
+        let events = match &row.tx_error {
+            Some(error) => {
+                replay.failed_attempts += 1;
+                Events {
+                    failed_attempts: vec![FailedAttempt {
+                        event: Some(event),
+                        error: error.clone(),
+                    }],
+                    ..Default::default()
+                }
+            }
+            None => {
+                if event
+                    .event
+                    .as_ref()
+                    .and_then(|e| e.staked_total())
+                    .is_some()
+                {
+                    stale_tables.extend(POSITION_TABLES);
+                }
+                Events {
+                    events: vec![event],
+                    ..Default::default()
+                }
+            }
+        };
+
+        // Other events of the transaction already hold the plain incrementing
+        // keys, so key the recovered rows by where their payload was found.
+        tables.set_namespace_and_reset_counters(quarantine::position(&row));
+        build_tables(
+            &mut tables,
+            &events,
+            &Deltas::new(Vec::new()),
+            &Deltas::new(Vec::new()),
+            &Deltas::new(Vec::new()),
+            &Deltas::new(Vec::new()),
+            &MemoryStore::default(),
+        );
+        for table in STATE_TABLES {
+            if tables.tables.tables.remove(*table).is_some() {
+                stale_tables.push(table);
+            }
+        }
+        if !stale_tables.is_empty() {
+            replay.stale_tables.extend(stale_tables);
+            replay.resync_from = Some(replay.resync_from.map_or(row.slot, |s| s.min(row.slot)));
+        }
+        let key = quarantine::key(&row);
+        tables.delete_row("Error", &key);
+        tables.delete_row(quarantine::TABLE, &key);
+    }
+
+    replay.database_changes = tables.to_database_changes();
+    // Tables come out in hash order, keep the statements stable.
+    replay
+        .database_changes
+        .table_changes
+        .sort_by(|a, b| (&a.table, primary_key(a)).cmp(&(&b.table, primary_key(b))));
+    Ok(replay)
+}
+
+impl Replay {
+    /// Statements applying the changes, within a transaction for Postgres.
+    /// Rows are keyed by their `id` column, as in the schemas.
+    pub fn to_sql(&self, dialect: Dialect) -> String {
+        let mut sql = String::new();
+        if dialect == Dialect::Postgres {
+            sql.push_str("BEGIN;\n");
+        }
+        for change in self.database_changes.table_changes.iter() {
+            let table = identifier(&change.table);
+            let key = primary_key(change);
+            let condition = key
+                .iter()
+                .map(|(column, value)| {
+                    format!("{} = {}", identifier(column), literal(value, dialect))
+                })
+                .collect::<Vec<_>>()
+                .join(" AND ");
+            // Columns come out in hash order too.
+            let mut fields: Vec<_> = change
+                .fields
+                .iter()
+                .map(|field| (field.name.as_str(), field.new_value.as_str()))
+                .collect();
+            fields.sort();
+            let fields = fields.into_iter();
+            match change.operation() {
+                Operation::Create => {
+                    let (columns, values): (Vec<_>, Vec<_>) = key
+                        .iter()
+                        .copied()
+                        .chain(fields)
+                        .map(|(column, value)| (identifier(column), literal(value, dialect)))
+                        .unzip();
+                    sql.push_str(&format!(
+                        "INSERT INTO {table} ({}) VALUES ({});\n",
+                        columns.join(", "),
+                        values.join(", ")
+                    ));
+                }
+                Operation::Update => {
+                    let assignments = fields
+                        .map(|(column, value)| {
+                            format!("{} = {}", identifier(column), literal(value, dialect))
+                        })
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    if assignments.is_empty() {
+                        continue;
+                    }
+                    sql.push_str(&match dialect {
+                        Dialect::Postgres => {
+                            format!("UPDATE {table} SET {assignments} WHERE {condition};\n")
+                        }
+                        Dialect::ClickHouse => {
+                            format!("ALTER TABLE {table} UPDATE {assignments} WHERE {condition};\n")
+                        }
+                    });
+                }
+                Operation::Delete => {
+                    sql.push_str(&format!("DELETE FROM {table} WHERE {condition};\n"));
+                }
+                Operation::Unspecified => {}
+            }
+        }
+        if dialect == Dialect::Postgres {
+            sql.push_str("COMMIT;\n");
+        }
+        sql
+    }
+}
+
+/// Columns and values of the primary key of a change.
+fn primary_key(change: &TableChange) -> Vec<(&str, &str)> {
+    match &change.primary_key {
+        Some(PrimaryKey::Pk(key)) => vec![("id", key.as_str())],
+        Some(PrimaryKey::CompositePk(composite)) => {
+            let mut keys: Vec<_> = composite
+                .keys
+                .iter()
+                .map(|(column, value)| (column.as_str(), value.as_str()))
+                .collect();
+            keys.sort();
+            keys
+        }
+        None => Vec::new(),
+    }
+}
+
+fn identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// Decodes a quarantined payload with the current decoders.
+fn decode(error: &DecodeError, lenient: bool) -> Result<Decoded, decoder::DecodeError> {
+    let options = DecodeOptions {
+        slot: error.slot,
+        lenient,
+    };
+    match error.source() {
+        Source::Log => decoder::decode_program_data(&error.payload, &options),
+        Source::InnerInstruction => {
+            let data = BASE64_STANDARD
+                .decode(&error.payload)
+                .map_err(|_| decoder::DecodeError::Base64Decode)?;
+            decoder::decode_event_instruction_data(&data, &options)
+        }
+    }
+}
+
+/// A string literal, which both databases convert to the type of the column.
+fn literal(value: &str, dialect: Dialect) -> String {
+    let value = match dialect {
+        Dialect::Postgres => value.replace('\'', "''"),
+        Dialect::ClickHouse => value.replace('\\', "\\\\").replace('\'', "\\'"),
+    };
+    format!("'{value}'")
+}
+
+fn parse_row(line: &str) -> Result<DecodeError> {
+    let fields: Vec<&str> = line.split('\t').collect();
+    if fields.len() != COLUMNS.len() {
+        bail!(
+            "expected {} columns ({}), got {}",
+            COLUMNS.len(),
+            COLUMNS.join(", "),
+            fields.len()
+        );
+    }
+    let field = |index: usize| -> Result<&str> {
+        match fields[index] {
+            "\\N" => bail!("'{}' is null", COLUMNS[index]),
+            value => Ok(value),
+        }
+    };
+    let number = |index: usize| -> Result<u64> {
+        field(index)?
+            .parse()
+            .map_err(|e| anyhow!("invalid '{}': {e}", COLUMNS[index]))
+    };
+
+    let source = Source::from_str_name(field(5)?)
+        .ok_or_else(|| anyhow!("invalid 'source' '{}'", fields[5]))?;
+    let timestamp = match fields[4] {
+        "\\N" => None,
+        value => Some(
+            value
+                .parse()
+                .map_err(|e| anyhow!("invalid 'timestamp': {e}"))?,
+        ),
+    };
+    let tx_error = match fields[10] {
+        "\\N" => None,
+        value => Some(unescape(value)),
+    };
+    Ok(DecodeError {
+        program_id: field(0)?.to_string(),
+        slot: number(1)?,
+        tx_signature: field(2)?.to_string(),
+        tx_index: number(3)? as u32,
+        timestamp,
+        source: source as i32,
+        log_index: number(6)? as u32,
+        instruction_index: number(7)? as u32,
+        inner_instruction_index: number(8)? as u32,
+        payload: field(9)?.to_string(),
+        tx_error,
+        ..Default::default()
+    })
+}
+
+/// Reverts the backslash escapes of the text format of `COPY`, which
+/// ClickHouse's `TabSeparated` format shares.
+fn unescape(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => unescaped.push('\t'),
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some(other) => unescaped.push(other),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{GummyEvent, Pubkey, SetReferrer, Withdraw};
+    use crate::fixtures::PROGRAM_ID;
+
+    fn row(slot: u64, log_index: u32, event: &GummyEvent, tx_error: Option<&str>) -> String {
+        let payload = BASE64_STANDARD.encode(borsh::to_vec(event).unwrap());
+        format!(
+            "{PROGRAM_ID}\t{slot}\tsig{slot}\t0\t\\N\tLOG\t{log_index}\t0\t0\t{payload}\t{}",
+            tx_error.unwrap_or("\\N")
+        )
+    }
+
+    #[test]
+    fn replays_history_tables_only() {
+        let set_referrer = GummyEvent::SetReferrer(SetReferrer {
+            user: Pubkey([2; 32]),
+            old_referrer: Pubkey([3; 32]),
+            new_referrer: Pubkey([4; 32]),
+        });
+        let withdraw = GummyEvent::Withdraw(Withdraw {
+            user: Pubkey([2; 32]),
+            total_amount: 4,
+        });
+        let input = [
+            row(12, 3, &set_referrer, None),
+            row(11, 2, &withdraw, None),
+            // A failed transaction, with an escaped tab in its error.
+            row(10, 1, &set_referrer, Some("BorshIoError(\"a\\tb\")")),
+            format!("{PROGRAM_ID}\t13\tsig13\t0\t\\N\tLOG\t4\t0\t0\tAAAAAAAAAAAA\t\\N"),
+        ]
+        .join("\n");
+
+        let replay = replay(&input, false).unwrap();
+        assert_eq!(replay.recovered, 3);
+        assert_eq!(replay.failed_attempts, 1);
+        assert_eq!(replay.failed.len(), 1);
+        assert_eq!(
+            replay.stale_tables.into_iter().collect::<Vec<_>>(),
+            [
+                "DailyTotalValueLocked",
+                "Referrer",
+                "TotalValueLocked",
+                "UserPosition"
+            ]
+        );
+        assert_eq!(replay.resync_from, Some(11));
+
+        let tables: BTreeSet<_> = replay
+            .database_changes
+            .table_changes
+            .iter()
+            .map(|change| (change.table.as_str(), change.operation()))
+            .collect();
+        assert_eq!(
+            tables.into_iter().collect::<Vec<_>>(),
+            [
+                ("Error", Operation::Delete),
+                ("FailedAttempt", Operation::Create),
+                ("Quarantine", Operation::Delete),
+                ("ReferrerChange", Operation::Create),
+                ("WithdrawEvent", Operation::Create),
+            ]
+        );
+        let failed_attempt = replay
+            .database_changes
+            .table_changes
+            .iter()
+            .find(|change| change.table == "FailedAttempt")
+            .unwrap();
+        let error = failed_attempt
+            .fields
+            .iter()
+            .find(|field| field.name == "error")
+            .unwrap();
+        assert_eq!(error.new_value, "BorshIoError(\"a\tb\")");
+    }
+
+    #[test]
+    fn writes_sql_statements() {
+        let mut tables = TablesWithIncrementingKey::<DatabaseTables>::new();
+        tables
+            .create_or_replace_row("WithdrawEvent", "it's")
+            .set("user", "a\\b")
+            .set("emergency", false);
+        tables
+            .update_row("UserPosition", "key")
+            .set("total_amount", 5);
+        tables.delete_row("Quarantine", "key");
+        let mut replay = replay("", false).unwrap();
+        replay.database_changes = tables.to_database_changes();
+        replay
+            .database_changes
+            .table_changes
+            .sort_by(|a, b| a.table.cmp(&b.table));
+
+        assert_eq!(
+            replay.to_sql(Dialect::Postgres),
+            "BEGIN;\n\
+             DELETE FROM \"Quarantine\" WHERE \"id\" = 'key';\n\
+             UPDATE \"UserPosition\" SET \"total_amount\" = '5' WHERE \"id\" = 'key';\n\
+             INSERT INTO \"WithdrawEvent\" (\"id\", \"emergency\", \"user\") \
+             VALUES ('it''s', 'false', 'a\\b');\n\
+             COMMIT;\n"
+        );
+        assert_eq!(
+            replay.to_sql(Dialect::ClickHouse),
+            "DELETE FROM \"Quarantine\" WHERE \"id\" = 'key';\n\
+             ALTER TABLE \"UserPosition\" UPDATE \"total_amount\" = '5' WHERE \"id\" = 'key';\n\
+             INSERT INTO \"WithdrawEvent\" (\"id\", \"emergency\", \"user\") \
+             VALUES ('it\\'s', 'false', 'a\\\\b');\n"
+        );
+    }
+}
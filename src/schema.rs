@@ -0,0 +1,194 @@
+//! DDL of the tables written for events of the IDL without a message of
+//! their own, which `build_tables` names after the event and fills from an
+//! `IdlEvent`. The schemas hold it between `IDL_TABLES_BEGIN` and
+//! `IDL_TABLES_END`, which the tests check, and rewrite with
+//! `UPDATE_SNAPSHOTS=1`.
+
+use crate::events::{ColumnType, EventInfo, EVENTS};
+
+/// The SQL flavor of the statements.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Dialect {
+    Postgres,
+    ClickHouse,
+}
+
+#[cfg(test)]
+const IDL_TABLES_BEGIN: &str =
+    "-- Tables of the IDL events decoded into IdlEvent, generated by src/schema.rs.\n";
+#[cfg(test)]
+const IDL_TABLES_END: &str = "-- End of the IDL event tables.\n";
+
+/// Columns `build_tables` sets on every row of an `IdlEvent`, before those of
+/// its fields.
+const EVENT_COLUMNS: &[(&str, &str, &str)] = &[
+    ("id", "TEXT NOT NULL PRIMARY KEY", "String"),
+    ("program_id", "TEXT NOT NULL", "String"),
+    ("slot", "NUMERIC NOT NULL", "UInt64"),
+    ("timestamp", "BIGINT", "Nullable(Int64)"),
+    ("tx_signature", "TEXT NOT NULL", "String"),
+];
+
+/// DDL of the tables of every event decoded into an `IdlEvent`.
+pub fn idl_tables(dialect: Dialect) -> String {
+    EVENTS
+        .iter()
+        .filter(|event| !event.message)
+        .map(|event| idl_table(event, dialect))
+        .collect()
+}
+
+fn idl_table(event: &EventInfo, dialect: Dialect) -> String {
+    let mut columns: Vec<String> = EVENT_COLUMNS
+        .iter()
+        .map(|(name, postgres, clickhouse)| match dialect {
+            Dialect::Postgres => format!("\"{name}\" {postgres}"),
+            Dialect::ClickHouse => format!("{name} {clickhouse}"),
+        })
+        .collect();
+    for column in event.columns {
+        // Fields named like an event column overwrite it.
+        if EVENT_COLUMNS.iter().any(|(name, ..)| *name == column.name) {
+            continue;
+        }
+        columns.push(match dialect {
+            Dialect::Postgres => format!(
+                "\"{}\" {}{}",
+                column.name,
+                postgres_type(column.column_type),
+                if column.nullable { "" } else { " NOT NULL" }
+            ),
+            Dialect::ClickHouse => {
+                let column_type = clickhouse_type(column.column_type);
+                if column.nullable {
+                    format!("{} Nullable({column_type})", column.name)
+                } else {
+                    format!("{} {column_type}", column.name)
+                }
+            }
+        });
+    }
+    let columns = columns.join(",\n    ");
+    match dialect {
+        Dialect::Postgres => format!(
+            "CREATE TABLE IF NOT EXISTS \"{}\" (\n    {columns}\n);\n\n",
+            event.name
+        ),
+        Dialect::ClickHouse => format!(
+            "CREATE TABLE IF NOT EXISTS {} (\n    {columns}\n) ENGINE = ReplacingMergeTree()\nORDER BY (id);\n\n",
+            event.name
+        ),
+    }
+}
+
+/// Integers that do not fit a signed column of the same size are `NUMERIC`,
+/// as amounts are in the other tables.
+fn postgres_type(column_type: ColumnType) -> &'static str {
+    match column_type {
+        ColumnType::Bool => "BOOLEAN",
+        ColumnType::Integer { signed, bits } => match (signed, bits) {
+            (_, 0..=16) | (true, 32) => "INTEGER",
+            (false, 32) | (true, 64) => "BIGINT",
+            _ => "NUMERIC",
+        },
+        ColumnType::Text => "TEXT",
+    }
+}
+
+fn clickhouse_type(column_type: ColumnType) -> String {
+    match column_type {
+        ColumnType::Bool => "Bool".to_string(),
+        ColumnType::Integer { signed: true, bits } => format!("Int{bits}"),
+        ColumnType::Integer {
+            signed: false,
+            bits,
+        } => format!("UInt{bits}"),
+        ColumnType::Text => "String".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn schemas_have_the_idl_tables() {
+        for (path, dialect) in [
+            ("schema.sql", Dialect::Postgres),
+            ("schema.clickhouse.sql", Dialect::ClickHouse),
+        ] {
+            let path = format!("{}/{path}", env!("CARGO_MANIFEST_DIR"));
+            let schema = std::fs::read_to_string(&path).unwrap();
+            let (before, rest) = schema
+                .split_once(IDL_TABLES_BEGIN)
+                .unwrap_or_else(|| panic!("{path}: missing '{}'", IDL_TABLES_BEGIN.trim()));
+            let (tables, after) = rest
+                .split_once(IDL_TABLES_END)
+                .unwrap_or_else(|| panic!("{path}: missing '{}'", IDL_TABLES_END.trim()));
+            let expected = idl_tables(dialect);
+            if tables == expected {
+                continue;
+            }
+            if std::env::var_os("UPDATE_SNAPSHOTS").is_some() {
+                std::fs::write(
+                    &path,
+                    format!("{before}{IDL_TABLES_BEGIN}{expected}{IDL_TABLES_END}{after}"),
+                )
+                .unwrap();
+            } else {
+                panic!("{path} is out of date with the IDL, rerun with UPDATE_SNAPSHOTS=1");
+            }
+        }
+    }
+
+    #[test]
+    fn writes_a_column_per_field() {
+        let deposit = EVENTS.iter().find(|event| event.name == "Deposit").unwrap();
+        assert_eq!(
+            idl_table(deposit, Dialect::Postgres),
+            r#"CREATE TABLE IF NOT EXISTS "Deposit" (
+    "id" TEXT NOT NULL PRIMARY KEY,
+    "program_id" TEXT NOT NULL,
+    "slot" NUMERIC NOT NULL,
+    "timestamp" BIGINT,
+    "tx_signature" TEXT NOT NULL,
+    "user" TEXT NOT NULL,
+    "amount" NUMERIC NOT NULL,
+    "total_amount" NUMERIC NOT NULL,
+    "lock_expires" BIGINT NOT NULL,
+    "referrer" TEXT NOT NULL
+);
+
+"#
+        );
+        assert_eq!(
+            idl_table(deposit, Dialect::ClickHouse),
+            "CREATE TABLE IF NOT EXISTS Deposit (
+    id String,
+    program_id String,
+    slot UInt64,
+    timestamp Nullable(Int64),
+    tx_signature String,
+    user String,
+    amount UInt64,
+    total_amount UInt64,
+    lock_expires UInt32,
+    referrer String
+) ENGINE = ReplacingMergeTree()
+ORDER BY (id);
+
+"
+        );
+    }
+
+    #[test]
+    fn maps_integers_to_columns_holding_them() {
+        let integer = |signed, bits| ColumnType::Integer { signed, bits };
+        assert_eq!(postgres_type(integer(false, 8)), "INTEGER");
+        assert_eq!(postgres_type(integer(false, 32)), "BIGINT");
+        assert_eq!(postgres_type(integer(true, 64)), "BIGINT");
+        assert_eq!(postgres_type(integer(false, 64)), "NUMERIC");
+        assert_eq!(postgres_type(integer(true, 128)), "NUMERIC");
+        assert_eq!(clickhouse_type(integer(true, 128)), "Int128");
+    }
+}
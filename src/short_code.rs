@@ -0,0 +1,158 @@
+//! Short referrer codes are arbitrary bytes on chain. They are keyed by their
+//! hex encoding, so distinct codes never share a row, and displayed in a
+//! case folded form that is flagged when another code shares it.
+
+use base64::prelude::*;
+use caseless::Caseless;
+use substreams::Hex;
+use unicode_normalization::UnicodeNormalization;
+
+pub struct ShortCode {
+    /// Lossless encoding of the raw bytes, used in keys.
+    pub hex: String,
+    pub base64: String,
+    /// Trimmed, case folded with the Unicode compatibility caseless matching
+    /// rules, then NFC normalized, so codes a user would read the same way
+    /// share it. Invalid UTF-8 sequences are replaced with `U+FFFD`.
+    pub display: String,
+    pub status: ShortCodeStatus,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ShortCodeStatus {
+    /// No other code was registered with the same display form before.
+    Valid,
+    /// The code is not valid UTF-8, its display form is lossy.
+    InvalidUtf8,
+    /// A different code was registered with the same display form before, so
+    /// the display form does not tell them apart.
+    Ambiguous,
+}
+
+impl ShortCode {
+    /// The code as seen on its own, `Valid` unless it is not UTF-8. See
+    /// `with_first_code` for collisions.
+    pub fn new(short: &[u8]) -> Self {
+        let (display, status) = match std::str::from_utf8(short) {
+            Ok(code) => (fold(code), ShortCodeStatus::Valid),
+            Err(_) => (
+                fold(&String::from_utf8_lossy(short)),
+                ShortCodeStatus::InvalidUtf8,
+            ),
+        };
+        ShortCode {
+            hex: Hex(short).to_string(),
+            base64: BASE64_STANDARD.encode(short),
+            display,
+            status,
+        }
+    }
+
+    /// Flags a valid code as ambiguous if `first_code`, the hex form of the
+    /// first code registered with the same display form, is another code.
+    pub fn with_first_code(mut self, first_code: Option<&str>) -> Self {
+        if self.status == ShortCodeStatus::Valid
+            && first_code.is_some_and(|first_code| first_code != self.hex)
+        {
+            self.status = ShortCodeStatus::Ambiguous;
+        }
+        self
+    }
+}
+
+/// `NFC(NFKD(fold(NFKD(fold(NFD(code))))))`, composed back so it reads like
+/// the original.
+fn fold(code: &str) -> String {
+    code.trim()
+        .chars()
+        .nfd()
+        .default_case_fold()
+        .nfkd()
+        .default_case_fold()
+        .nfkd()
+        .nfc()
+        .collect()
+}
+
+impl ShortCodeStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ShortCodeStatus::Valid => "valid",
+            ShortCodeStatus::InvalidUtf8 => "invalid_utf8",
+            ShortCodeStatus::Ambiguous => "ambiguous",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_the_raw_bytes() {
+        let short = ShortCode::new(b" Gummy");
+        assert_eq!(short.hex, "2047756d6d79");
+        assert_eq!(short.base64, "IEd1bW15");
+        assert_eq!(short.display, "gummy");
+        assert_eq!(short.status, ShortCodeStatus::Valid);
+    }
+
+    #[test]
+    fn folds_case_beyond_lowercasing() {
+        for (code, display) in [
+            ("GUMMY", "gummy"),
+            // Lowercasing leaves `ß`, `ſ` and the final sigma as they are.
+            ("Straße", "strasse"),
+            ("STRASSE", "strasse"),
+            ("ſtake", "stake"),
+            ("ΣΑΣ", "σασ"),
+            ("σας", "σασ"),
+            // Compatibility forms.
+            ("ﬀ", "ff"),
+            ("ｇｕｍｍｙ", "gummy"),
+            // Composed and decomposed forms.
+            ("caf\u{e9}", "caf\u{e9}"),
+            ("cafe\u{301}", "caf\u{e9}"),
+            ("CAF\u{c9}", "caf\u{e9}"),
+        ] {
+            assert_eq!(ShortCode::new(code.as_bytes()).display, display, "{code}");
+        }
+    }
+
+    #[test]
+    fn invalid_utf8_is_displayed_lossily() {
+        let short = ShortCode::new(b"gu\xffmy");
+        assert_eq!(short.display, "gu\u{fffd}my");
+        assert_eq!(short.status, ShortCodeStatus::InvalidUtf8);
+        assert_eq!(short.hex, "6775ff6d79");
+    }
+
+    #[test]
+    fn flags_codes_sharing_the_display_of_an_earlier_one() {
+        let first = ShortCode::new(b"gummy");
+        let second = ShortCode::new(b"GUMMY");
+        assert_eq!(first.display, second.display);
+        assert_ne!(first.hex, second.hex);
+
+        let first_code = Some(first.hex.as_str());
+        assert_eq!(
+            ShortCode::new(b"gummy").with_first_code(first_code).status,
+            ShortCodeStatus::Valid
+        );
+        assert_eq!(
+            ShortCode::new(b"GUMMY").with_first_code(first_code).status,
+            ShortCodeStatus::Ambiguous
+        );
+        assert_eq!(
+            ShortCode::new(b"GUMMY").with_first_code(None).status,
+            ShortCodeStatus::Valid
+        );
+        // Invalid UTF-8 is the more useful flag of the two.
+        assert_eq!(
+            ShortCode::new(b"gummy\xff")
+                .with_first_code(first_code)
+                .status,
+            ShortCodeStatus::InvalidUtf8
+        );
+    }
+}
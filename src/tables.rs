@@ -0,0 +1,102 @@
+//! Common surface of the entity and database change tables, so the same row
+//! building code can back both `map_events` and `db_out`.
+
+use substreams_database_change::tables::ToDatabaseValue;
+use substreams_entity_change::tables::ToValue;
+
+pub type EntityTables = substreams_entity_change::tables::Tables;
+pub type DatabaseTables = substreams_database_change::tables::Tables;
+
+/// A value that can be written to both entity and database rows.
+pub trait Value: ToValue + ToDatabaseValue {}
+impl<T: ToValue + ToDatabaseValue> Value for T {}
+
+pub trait Tables {
+    type Row: Row;
+
+    fn new() -> Self;
+    fn create_row(&mut self, table: &str, key: &str) -> &mut Self::Row;
+    fn update_row(&mut self, table: &str, key: &str) -> &mut Self::Row;
+    fn delete_row(&mut self, table: &str, key: &str) -> &mut Self::Row;
+    /// Writes a row the block already updated or deleted, where `create_row`
+    /// panics. The earlier change is dropped, and the row is sent as created
+    /// if `created`, as an update of the columns set from now on otherwise.
+    fn replace_row(&mut self, table: &str, key: &str, created: bool) -> &mut Self::Row;
+}
+
+pub trait Row {
+    fn set<T: Value>(&mut self, name: &str, value: T) -> &mut Self;
+
+    fn set_if_some<T: Value>(&mut self, name: &str, value: Option<T>) -> &mut Self {
+        match value {
+            Some(value) => self.set(name, value),
+            None => self,
+        }
+    }
+}
+
+impl Tables for EntityTables {
+    type Row = substreams_entity_change::tables::Row;
+
+    fn new() -> Self {
+        EntityTables::new()
+    }
+    fn create_row(&mut self, table: &str, key: &str) -> &mut Self::Row {
+        EntityTables::create_row(self, table, key)
+    }
+    fn update_row(&mut self, table: &str, key: &str) -> &mut Self::Row {
+        EntityTables::update_row(self, table, key)
+    }
+    fn delete_row(&mut self, table: &str, key: &str) -> &mut Self::Row {
+        EntityTables::delete_row(self, table, key)
+    }
+    fn replace_row(&mut self, table: &str, key: &str, created: bool) -> &mut Self::Row {
+        use substreams_entity_change::pb::entity::entity_change::Operation;
+        let row = EntityTables::delete_row(self, table, key);
+        row.operation = if created {
+            Operation::Create
+        } else {
+            Operation::Update
+        };
+        row
+    }
+}
+
+impl Row for substreams_entity_change::tables::Row {
+    fn set<T: Value>(&mut self, name: &str, value: T) -> &mut Self {
+        substreams_entity_change::tables::Row::set(self, name, value)
+    }
+}
+
+impl Tables for DatabaseTables {
+    type Row = substreams_database_change::tables::Row;
+
+    fn new() -> Self {
+        DatabaseTables::new()
+    }
+    fn create_row(&mut self, table: &str, key: &str) -> &mut Self::Row {
+        DatabaseTables::create_row(self, table, key)
+    }
+    fn update_row(&mut self, table: &str, key: &str) -> &mut Self::Row {
+        DatabaseTables::update_row(self, table, key)
+    }
+    fn delete_row(&mut self, table: &str, key: &str) -> &mut Self::Row {
+        DatabaseTables::delete_row::<&str>(self, table, key.into())
+    }
+    fn replace_row(&mut self, table: &str, key: &str, created: bool) -> &mut Self::Row {
+        use substreams_database_change::pb::database::table_change::Operation;
+        let row = DatabaseTables::delete_row::<&str>(self, table, key.into());
+        row.operation = if created {
+            Operation::Create
+        } else {
+            Operation::Update
+        };
+        row
+    }
+}
+
+impl Row for substreams_database_change::tables::Row {
+    fn set<T: Value>(&mut self, name: &str, value: T) -> &mut Self {
+        substreams_database_change::tables::Row::set(self, name, value)
+    }
+}
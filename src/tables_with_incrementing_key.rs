@@ -1,44 +1,108 @@
+use crate::tables::{DatabaseTables, EntityTables, Row, Tables};
 use std::collections::HashMap;
+use substreams_database_change::pb::database::DatabaseChanges;
 use substreams_entity_change::pb::entity::EntityChanges;
-use substreams_entity_change::tables::Row;
-use substreams_entity_change::tables::Tables;
 
-pub struct TablesWithIncrementingKey {
-    pub tables: Tables,
+pub struct TablesWithIncrementingKey<T: Tables> {
+    pub tables: T,
     prefix: String,
+    namespace: String,
     counters: HashMap<String, u64>,
+    /// Table and key of the rows written through `create_or_replace_row`,
+    /// `update_row` and `delete_row`, and whether the block created them.
+    rows: HashMap<(String, String), bool>,
 }
 
-impl TablesWithIncrementingKey {
+impl<T: Tables> TablesWithIncrementingKey<T> {
     pub fn new() -> Self {
         TablesWithIncrementingKey {
-            tables: Tables::new(),
+            tables: T::new(),
             prefix: "".to_string(),
+            namespace: "".to_string(),
             counters: HashMap::new(),
+            rows: HashMap::new(),
         }
     }
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
     pub fn set_prefix_and_reset_counters(&mut self, prefix: String) {
         self.prefix = prefix;
         self.counters.clear();
     }
 
-    pub fn create_row_with_incrementing_key(&mut self, table: &str) -> &mut Row {
+    /// Inserts `namespace` between the prefix and the counter, so rows created
+    /// apart from the others of their prefix do not reuse their keys.
+    #[cfg(any(test, feature = "replay"))]
+    pub fn set_namespace_and_reset_counters(&mut self, namespace: String) {
+        self.namespace = namespace;
+        self.counters.clear();
+    }
+
+    pub fn create_row_with_incrementing_key(&mut self, table: &str) -> &mut T::Row {
         let counter = self
             .counters
             .entry(table.to_string())
             .and_modify(|c| *c += 1)
             .or_insert(1);
-        let key = format!("{}-{}", self.prefix, counter);
+        let key = if self.namespace.is_empty() {
+            format!("{}-{}", self.prefix, counter)
+        } else {
+            format!("{}-{}-{}", self.prefix, self.namespace, counter)
+        };
+
+        self.tables.create_row(table, &key)
+    }
+
+    /// Creates a row, or replaces it if it was written earlier in the block,
+    /// as `create_row` panics on a row updated or deleted before.
+    pub fn create_or_replace_row(&mut self, table: &str, key: &str) -> &mut T::Row {
+        match self.rows.get(&(table.to_string(), key.to_string())) {
+            Some(&created) => self.tables.replace_row(table, key, created),
+            None => {
+                self.rows.insert((table.to_string(), key.to_string()), true);
+                self.tables.create_row(table, key)
+            }
+        }
+    }
 
-        self.tables.create_row(table, key)
+    pub fn update_row(&mut self, table: &str, key: &str) -> &mut T::Row {
+        self.rows
+            .entry((table.to_string(), key.to_string()))
+            .or_insert(false);
+        self.tables.update_row(table, key)
     }
 
-    pub fn log_error(&mut self, error: &str) {
+    pub fn delete_row(&mut self, table: &str, key: &str) -> &mut T::Row {
+        self.rows
+            .entry((table.to_string(), key.to_string()))
+            .or_insert(false);
+        self.tables.delete_row(table, key)
+    }
+
+    pub fn log_error(&mut self, error: &str) -> &mut T::Row {
         self.create_row_with_incrementing_key("Error")
-            .set("description", error);
+            .set("description", error)
     }
+}
 
+impl<T: Tables> Default for TablesWithIncrementingKey<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TablesWithIncrementingKey<EntityTables> {
+    #[allow(clippy::wrong_self_convention)]
     pub fn to_entity_changes(self) -> EntityChanges {
         self.tables.to_entity_changes()
     }
 }
+
+impl TablesWithIncrementingKey<DatabaseTables> {
+    #[allow(clippy::wrong_self_convention)]
+    pub fn to_database_changes(self) -> DatabaseChanges {
+        self.tables.to_database_changes()
+    }
+}
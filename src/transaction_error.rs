@@ -0,0 +1,310 @@
+//! Decoding of `TransactionStatusMeta::err`, which holds the bincode encoding
+//! of the runtime's `TransactionError`.
+
+use substreams::Hex;
+
+const TRANSACTION_ERRORS: &[&str] = &[
+    "AccountInUse",
+    "AccountLoadedTwice",
+    "AccountNotFound",
+    "ProgramAccountNotFound",
+    "InsufficientFundsForFee",
+    "InvalidAccountForFee",
+    "AlreadyProcessed",
+    "BlockhashNotFound",
+    "InstructionError",
+    "CallChainTooDeep",
+    "MissingSignatureForFee",
+    "InvalidAccountIndex",
+    "SignatureFailure",
+    "InvalidProgramForExecution",
+    "SanitizeFailure",
+    "ClusterMaintenance",
+    "AccountBorrowOutstanding",
+    "WouldExceedMaxBlockCostLimit",
+    "UnsupportedVersion",
+    "InvalidWritableAccount",
+    "WouldExceedMaxAccountCostLimit",
+    "WouldExceedAccountDataBlockLimit",
+    "TooManyAccountLocks",
+    "AddressLookupTableNotFound",
+    "InvalidAddressLookupTableOwner",
+    "InvalidAddressLookupTableData",
+    "InvalidAddressLookupTableIndex",
+    "InvalidRentPayingAccount",
+    "WouldExceedMaxVoteCostLimit",
+    "WouldExceedAccountDataTotalLimit",
+    "DuplicateInstruction",
+    "InsufficientFundsForRent",
+    "MaxLoadedAccountsDataSizeExceeded",
+    "InvalidLoadedAccountsDataSizeLimit",
+    "ResanitizationNeeded",
+    "ProgramExecutionTemporarilyRestricted",
+    "UnbalancedTransaction",
+    "ProgramCacheHitMaxLimit",
+];
+
+const INSTRUCTION_ERRORS: &[&str] = &[
+    "GenericError",
+    "InvalidArgument",
+    "InvalidInstructionData",
+    "InvalidAccountData",
+    "AccountDataTooSmall",
+    "InsufficientFunds",
+    "IncorrectProgramId",
+    "MissingRequiredSignature",
+    "AccountAlreadyInitialized",
+    "UninitializedAccount",
+    "UnbalancedInstruction",
+    "ModifiedProgramId",
+    "ExternalAccountLamportSpend",
+    "ExternalAccountDataModified",
+    "ReadonlyLamportChange",
+    "ReadonlyDataModified",
+    "DuplicateAccountIndex",
+    "ExecutableModified",
+    "RentEpochModified",
+    "NotEnoughAccountKeys",
+    "AccountDataSizeChanged",
+    "AccountNotExecutable",
+    "AccountBorrowFailed",
+    "AccountBorrowOutstanding",
+    "DuplicateAccountOutOfSync",
+    "Custom",
+    "InvalidError",
+    "ExecutableDataModified",
+    "ExecutableLamportChange",
+    "ExecutableAccountNotRentExempt",
+    "UnsupportedProgramId",
+    "CallDepth",
+    "MissingAccount",
+    "ReentrancyNotAllowed",
+    "MaxSeedLengthExceeded",
+    "InvalidSeeds",
+    "InvalidRealloc",
+    "ComputationalBudgetExceeded",
+    "PrivilegeEscalation",
+    "ProgramEnvironmentSetupFailure",
+    "ProgramFailedToComplete",
+    "ProgramFailedToCompile",
+    "Immutable",
+    "IncorrectAuthority",
+    "BorshIoError",
+    "AccountNotRentExempt",
+    "InvalidAccountOwner",
+    "ArithmeticOverflow",
+    "UnsupportedSysvar",
+    "IllegalOwner",
+    "MaxAccountsDataAllocationsExceeded",
+    "MaxAccountsResizesExceeded",
+    "MaxInstructionTraceLengthExceeded",
+    "BuiltinProgramsMustConsumeComputeUnits",
+];
+
+const INSTRUCTION_ERROR: u32 = 8;
+const DUPLICATE_INSTRUCTION: u32 = 30;
+const INSUFFICIENT_FUNDS_FOR_RENT: u32 = 31;
+const PROGRAM_EXECUTION_TEMPORARILY_RESTRICTED: u32 = 35;
+
+const CUSTOM: u32 = 25;
+const BORSH_IO_ERROR: u32 = 44;
+
+/// Renders the error the way the runtime's `Debug` implementation does, e.g.
+/// `InstructionError(1, Custom(6001))`. Unknown or malformed encodings fall
+/// back to their hex representation.
+pub fn describe(err: &[u8]) -> String {
+    let mut reader = Reader(err);
+    match describe_transaction_error(&mut reader) {
+        Some(description) if reader.0.is_empty() => description,
+        _ => format!("0x{}", Hex(err)),
+    }
+}
+
+fn describe_transaction_error(reader: &mut Reader) -> Option<String> {
+    let variant = reader.u32()?;
+    let name = TRANSACTION_ERRORS.get(variant as usize)?;
+    Some(match variant {
+        INSTRUCTION_ERROR => {
+            let index = reader.u8()?;
+            let error = describe_instruction_error(reader)?;
+            format!("{name}({index}, {error})")
+        }
+        DUPLICATE_INSTRUCTION => format!("{name}({})", reader.u8()?),
+        INSUFFICIENT_FUNDS_FOR_RENT | PROGRAM_EXECUTION_TEMPORARILY_RESTRICTED => {
+            format!("{name} {{ account_index: {} }}", reader.u8()?)
+        }
+        _ => name.to_string(),
+    })
+}
+
+fn describe_instruction_error(reader: &mut Reader) -> Option<String> {
+    let variant = reader.u32()?;
+    let name = INSTRUCTION_ERRORS.get(variant as usize)?;
+    Some(match variant {
+        CUSTOM => format!("{name}({})", reader.u32()?),
+        BORSH_IO_ERROR => format!("{name}({:?})", reader.string()?),
+        _ => name.to_string(),
+    })
+}
+
+/// The tag of the transaction error variant `name` in its bincode encoding.
+#[cfg(any(test, feature = "fixtures"))]
+pub(crate) fn transaction_error_tag(name: &str) -> Option<u32> {
+    TRANSACTION_ERRORS
+        .iter()
+        .position(|error| *error == name)
+        .map(|tag| tag as u32)
+}
+
+/// The tag of the instruction error variant `name` in its bincode encoding.
+#[cfg(any(test, feature = "fixtures"))]
+pub(crate) fn instruction_error_tag(name: &str) -> Option<u32> {
+    INSTRUCTION_ERRORS
+        .iter()
+        .position(|error| *error == name)
+        .map(|tag| tag as u32)
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn take(&mut self, len: usize) -> Option<&[u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+        let (head, tail) = self.0.split_at(len);
+        self.0 = tail;
+        Some(head)
+    }
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|bytes| bytes[0])
+    }
+    fn u32(&mut self) -> Option<u32> {
+        self.take(4)
+            .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+    }
+    fn u64(&mut self) -> Option<u64> {
+        self.take(8)
+            .map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+    }
+    fn string(&mut self) -> Option<String> {
+        let len = usize::try_from(self.u64()?).ok()?;
+        self.take(len)
+            .map(|bytes| String::from_utf8_lossy(bytes).into_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures;
+
+    /// Concatenates little endian fields into a bincode encoding.
+    fn encode(fields: &[&[u8]]) -> Vec<u8> {
+        fields.concat()
+    }
+
+    #[test]
+    fn describes_every_variant_shape() {
+        let borsh_io_error = encode(&[
+            &8u32.to_le_bytes(),
+            &[2],
+            &44u32.to_le_bytes(),
+            &7u64.to_le_bytes(),
+            b"Unknown",
+        ]);
+        let cases: &[(Vec<u8>, &str)] = &[
+            (encode(&[&0u32.to_le_bytes()]), "AccountInUse"),
+            (encode(&[&7u32.to_le_bytes()]), "BlockhashNotFound"),
+            (encode(&[&37u32.to_le_bytes()]), "ProgramCacheHitMaxLimit"),
+            (
+                encode(&[&8u32.to_le_bytes(), &[0], &0u32.to_le_bytes()]),
+                "InstructionError(0, GenericError)",
+            ),
+            (
+                encode(&[
+                    &8u32.to_le_bytes(),
+                    &[1],
+                    &25u32.to_le_bytes(),
+                    &6001u32.to_le_bytes(),
+                ]),
+                "InstructionError(1, Custom(6001))",
+            ),
+            (
+                borsh_io_error,
+                "InstructionError(2, BorshIoError(\"Unknown\"))",
+            ),
+            (
+                encode(&[&8u32.to_le_bytes(), &[3], &53u32.to_le_bytes()]),
+                "InstructionError(3, BuiltinProgramsMustConsumeComputeUnits)",
+            ),
+            (
+                encode(&[&30u32.to_le_bytes(), &[4]]),
+                "DuplicateInstruction(4)",
+            ),
+            (
+                encode(&[&31u32.to_le_bytes(), &[5]]),
+                "InsufficientFundsForRent { account_index: 5 }",
+            ),
+            (
+                encode(&[&35u32.to_le_bytes(), &[6]]),
+                "ProgramExecutionTemporarilyRestricted { account_index: 6 }",
+            ),
+        ];
+        for (err, expected) in cases {
+            assert_eq!(describe(err), *expected, "{}", Hex(err));
+        }
+    }
+
+    #[test]
+    fn falls_back_to_hex_on_truncated_or_unknown_input() {
+        let cases: &[&[u8]] = &[
+            b"",
+            // A partial variant tag.
+            &[8, 0],
+            // `InstructionError` without its index, then without its error.
+            &[8, 0, 0, 0],
+            &[8, 0, 0, 0, 1],
+            // `Custom` without its code, then with half of it.
+            &[8, 0, 0, 0, 1, 25, 0, 0, 0],
+            &[8, 0, 0, 0, 1, 25, 0, 0, 0, 0x71, 0x17],
+            // `BorshIoError` with a string longer than the input.
+            &[8, 0, 0, 0, 1, 44, 0, 0, 0, 9, 0, 0, 0, 0, 0, 0, 0, b'x'],
+            // The u8 payload variants without their payload.
+            &[30, 0, 0, 0],
+            &[31, 0, 0, 0],
+            &[35, 0, 0, 0],
+            // Unknown transaction and instruction errors.
+            &[38, 0, 0, 0],
+            &[0xff, 0xff, 0xff, 0xff],
+            &[8, 0, 0, 0, 1, 54, 0, 0, 0],
+            // Trailing bytes after a complete error.
+            &[0, 0, 0, 0, 0],
+        ];
+        for err in cases {
+            assert_eq!(describe(err), format!("0x{}", Hex(err)), "{}", Hex(err));
+        }
+    }
+
+    #[test]
+    fn encodes_transaction_errors_as_the_runtime_does() {
+        let described = |err: Option<Vec<u8>>| describe(&err.unwrap());
+        assert_eq!(
+            described(fixtures::transaction_error("AccountInUse", &[])),
+            "AccountInUse"
+        );
+        assert_eq!(
+            described(fixtures::transaction_error(
+                "InstructionError",
+                &fixtures::instruction_error(1, "Custom", &6001u32.to_le_bytes()).unwrap()
+            )),
+            "InstructionError(1, Custom(6001))"
+        );
+        assert_eq!(
+            described(fixtures::transaction_error("DuplicateInstruction", &[4])),
+            "DuplicateInstruction(4)"
+        );
+        assert_eq!(fixtures::transaction_error("NoSuchError", &[]), None);
+        assert_eq!(fixtures::instruction_error(0, "NoSuchError", &[]), None);
+    }
+}
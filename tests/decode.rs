@@ -0,0 +1,177 @@
+//! Decodes payloads through the public API, which services outside of the
+//! substreams use, and checks it agrees with `map_gummy_events`.
+
+use gummy_staking::events::{
+    AdminDeleteShortReferrer, AdminEmergencyWithdraw, AdminRegisterShortReferrer, Deposit, Pubkey,
+    RegisterShortReferrer, SetReferrer, Withdraw, DISCRIMINATOR_WITHDRAW,
+};
+use gummy_staking::fixtures::{program_data_line, program_data_line_from_bytes};
+use gummy_staking::messages::{event, Source};
+use gummy_staking::{
+    decode_program_data, decode_program_data_with_options, gummy_events, DecodeError,
+    DecodeOptions, GummyEvent,
+};
+use prost::Message;
+use std::path::Path;
+use substreams_solana::pb::sf::solana::r#type::v1::Block;
+
+const PREFIX: &str = "Program data: ";
+
+fn payload(line: &str) -> &str {
+    line.strip_prefix(PREFIX).unwrap()
+}
+
+fn events() -> Vec<GummyEvent> {
+    vec![
+        GummyEvent::Deposit(Deposit {
+            user: Pubkey([2; 32]),
+            amount: 10,
+            total_amount: 30,
+            lock_expires: 1_717_086_400,
+            referrer: Pubkey([3; 32]),
+        }),
+        GummyEvent::Withdraw(Withdraw {
+            user: Pubkey([2; 32]),
+            total_amount: 20,
+        }),
+        GummyEvent::SetReferrer(SetReferrer {
+            user: Pubkey([2; 32]),
+            old_referrer: Pubkey([3; 32]),
+            new_referrer: Pubkey([4; 32]),
+        }),
+        GummyEvent::RegisterShortReferrer(RegisterShortReferrer {
+            full: Pubkey([3; 32]),
+            short: b"bear".to_vec(),
+        }),
+        GummyEvent::AdminRegisterShortReferrer(AdminRegisterShortReferrer {
+            full: Pubkey([4; 32]),
+            short: b"\xff\xfe".to_vec(),
+            initiator: Pubkey([5; 32]),
+        }),
+        GummyEvent::AdminDeleteShortReferrer(AdminDeleteShortReferrer {
+            short: b"bear".to_vec(),
+            initiator: Pubkey([5; 32]),
+        }),
+        GummyEvent::AdminEmergencyWithdraw(AdminEmergencyWithdraw {
+            user: Pubkey([2; 32]),
+            total_amount: 0,
+            initiator: Pubkey([5; 32]),
+        }),
+    ]
+}
+
+#[test]
+fn decodes_every_event() {
+    for event in events() {
+        let line = program_data_line(&event);
+        assert_eq!(decode_program_data(payload(&line)), Ok(event.clone()));
+
+        let decoded =
+            decode_program_data_with_options(payload(&line), &DecodeOptions::default()).unwrap();
+        assert_eq!(decoded.event, event);
+        assert_eq!(decoded.layout_version, 1);
+        assert_eq!(decoded.ignored_bytes, 0);
+    }
+}
+
+#[test]
+fn trailing_bytes_are_only_accepted_when_lenient() {
+    let mut data = borsh::to_vec(&events()[1]).unwrap();
+    data.extend([0; 3]);
+    let line = program_data_line_from_bytes(&data);
+
+    assert_eq!(
+        decode_program_data(payload(&line)),
+        Err(DecodeError::TrailingBytes {
+            event: "Withdraw",
+            discriminator: DISCRIMINATOR_WITHDRAW.try_into().unwrap(),
+            count: 3,
+        })
+    );
+    let options = DecodeOptions {
+        slot: 0,
+        lenient: true,
+    };
+    let decoded = decode_program_data_with_options(payload(&line), &options).unwrap();
+    assert_eq!(decoded.event, events()[1]);
+    assert_eq!(decoded.ignored_bytes, 3);
+}
+
+#[test]
+fn rejects_malformed_payloads() {
+    assert_eq!(
+        decode_program_data("not base64!"),
+        Err(DecodeError::Base64Decode)
+    );
+    assert_eq!(
+        decode_program_data(payload(&program_data_line_from_bytes(&[1, 2, 3]))),
+        Err(DecodeError::TooShort { len: 3 })
+    );
+    assert_eq!(
+        decode_program_data(payload(&program_data_line_from_bytes(&[7; 16]))),
+        Err(DecodeError::UnknownDiscriminator {
+            discriminator: [7; 8]
+        })
+    );
+    assert!(matches!(
+        decode_program_data(payload(&program_data_line_from_bytes(
+            DISCRIMINATOR_WITHDRAW
+        ))),
+        Err(DecodeError::BorshDecode {
+            event: "Withdraw",
+            ..
+        })
+    ));
+}
+
+/// Every event `map_gummy_events` finds in the logs of the test blocks reads
+/// the same through the public API.
+#[test]
+fn agrees_with_map_gummy_events_on_the_test_blocks() {
+    let blocks = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/blocks");
+    let paths = ["mainnet", "synthetic"]
+        .into_iter()
+        .flat_map(|set| std::fs::read_dir(blocks.join(set)).unwrap())
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "binpb")
+        });
+    let mut checked = 0;
+    for path in paths {
+        let block = Block::decode(std::fs::read(path).unwrap().as_slice()).unwrap();
+        let output = gummy_events("record_failed_transactions=true", &block).unwrap();
+        let events = output.events.iter().chain(
+            output
+                .failed_attempts
+                .iter()
+                .filter_map(|a| a.event.as_ref()),
+        );
+        for event in events.filter(|event| event.source() == Source::Log) {
+            let log_messages = &block.transactions[event.tx_index as usize]
+                .meta
+                .as_ref()
+                .unwrap()
+                .log_messages;
+            // `map_gummy_events` decodes leniently by default.
+            let options = DecodeOptions {
+                slot: block.slot,
+                lenient: true,
+            };
+            let decoded = decode_program_data_with_options(
+                payload(&log_messages[event.log_index as usize]),
+                &options,
+            )
+            .unwrap();
+            assert_eq!(decoded.layout_version, event.layout_version);
+            assert_eq!(
+                Some(event::Event::from(decoded.event)),
+                event.event,
+                "{}",
+                event.tx_signature
+            );
+            checked += 1;
+        }
+    }
+    assert!(checked > 0);
+}
@@ -0,0 +1,146 @@
+//! Runs the blocks of `testdata/blocks`, in slot order, through the modules
+//! up to `map_events`, stores included, and compares the entity changes of
+//! each block with its snapshot in `testdata/snapshots`.
+//!
+//! Blocks are protobuf encoded `sf.solana.type.v1.Block` messages, in two
+//! sets run apart from each other: `mainnet`, blocks of the chain recorded
+//! with `make capture-block`, see `examples/capture_block.rs`, and
+//! `synthetic`, blocks covering cases the chain has not shown yet, written by
+//! `make fixtures`. Within a set, the stores keep their values from one block
+//! to the next, as if the substreams started at the first block, so adding a
+//! block can change the snapshots of the later ones. After a change in the
+//! output, review the differences and update the snapshots with
+//! `make update-snapshots`, which sets `UPDATE_SNAPSHOTS=1`.
+
+use gummy_staking::NativeModules;
+use prost::Message;
+use std::fmt::Write;
+use std::path::Path;
+use substreams_entity_change::pb::entity::EntityChanges;
+use substreams_solana::pb::sf::solana::r#type::v1::Block;
+
+const PARAMS: &str = "record_failed_transactions=true";
+
+/// Directories of `testdata/blocks` and `testdata/snapshots`, each run with
+/// stores of its own.
+const SETS: &[&str] = &["mainnet", "synthetic"];
+
+#[test]
+fn golden() {
+    let testdata = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata");
+    let update = std::env::var_os("UPDATE_SNAPSHOTS").is_some();
+
+    let mut mismatches = Vec::new();
+    let mut block_count = 0;
+    for set in SETS {
+        let blocks = blocks(&testdata.join("blocks").join(set));
+        block_count += blocks.len();
+
+        let mut modules = NativeModules::new(PARAMS);
+        for (name, block) in blocks {
+            let (_, changes) = modules.process(&block).unwrap();
+            let snapshot = render(&changes);
+
+            let snapshot_path = testdata
+                .join("snapshots")
+                .join(set)
+                .join(format!("{name}.snap"));
+            if update {
+                std::fs::write(&snapshot_path, &snapshot).unwrap();
+                continue;
+            }
+            match std::fs::read_to_string(&snapshot_path) {
+                Ok(expected) if expected == snapshot => {}
+                Ok(expected) => mismatches.push(format!(
+                    "{set}/{name}: snapshot differs\n{}",
+                    diff(&expected, &snapshot)
+                )),
+                Err(e) => {
+                    mismatches.push(format!("{set}/{name}: {}: {e}", snapshot_path.display()))
+                }
+            }
+        }
+    }
+    assert!(block_count > 0, "no blocks in testdata/blocks");
+    assert!(
+        mismatches.is_empty(),
+        "{}\nrun `make update-snapshots` if the changes are expected",
+        mismatches.join("\n")
+    );
+}
+
+/// The blocks of `dir`, by slot then name.
+fn blocks(dir: &Path) -> Vec<(String, Block)> {
+    let mut blocks: Vec<_> = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "binpb")
+        })
+        .map(|path| {
+            let name = path.file_stem().unwrap().to_str().unwrap().to_string();
+            let block = Block::decode(std::fs::read(&path).unwrap().as_slice()).unwrap();
+            (name, block)
+        })
+        .collect();
+    blocks.sort_by(|(a_name, a), (b_name, b)| (a.slot, a_name).cmp(&(b.slot, b_name)));
+    blocks
+}
+
+/// One line per change and per field, sorted, as the order of the changes
+/// within a table is not meaningful.
+fn render(changes: &EntityChanges) -> String {
+    let mut changes: Vec<_> = changes.entity_changes.iter().collect();
+    changes.sort_by(|a, b| (&a.entity, &a.id).cmp(&(&b.entity, &b.id)));
+    let mut out = String::new();
+    for change in changes {
+        writeln!(
+            out,
+            "{} {} {}",
+            change.entity,
+            change.id,
+            change.operation().as_str_name()
+        )
+        .unwrap();
+        let mut fields: Vec<_> = change.fields.iter().collect();
+        fields.sort_by(|a, b| a.name.cmp(&b.name));
+        for field in fields {
+            let value = field
+                .new_value
+                .as_ref()
+                .and_then(|value| value.typed.as_ref());
+            writeln!(out, "  {}: {value:?}", field.name).unwrap();
+        }
+    }
+    out
+}
+
+/// The first lines that differ, with the change they belong to.
+fn diff(expected: &str, actual: &str) -> String {
+    let expected_lines: Vec<_> = expected.lines().collect();
+    let actual_lines: Vec<_> = actual.lines().collect();
+    let first = expected_lines
+        .iter()
+        .zip(actual_lines.iter())
+        .position(|(expected, actual)| expected != actual)
+        .unwrap_or(expected_lines.len().min(actual_lines.len()));
+    // Start from the line naming the change.
+    let start = expected_lines[..first]
+        .iter()
+        .rposition(|line| !line.starts_with(' '))
+        .unwrap_or(0);
+    let excerpt = |lines: &[&str], prefix: &str| {
+        lines
+            .iter()
+            .skip(start)
+            .take(first - start + 5)
+            .map(|line| format!("{prefix} {line}\n"))
+            .collect::<String>()
+    };
+    format!(
+        "{}{}",
+        excerpt(&expected_lines, "-"),
+        excerpt(&actual_lines, "+")
+    )
+}